@@ -0,0 +1,11 @@
+struct String;
+trait From<T> {}
+trait Display {}
+trait ToString {}
+trait Describe {}
+
+impl<T: Display> ToString for T {}
+impl<T: ToString> Describe for T {}
+impl<T: Display> From<T> for String {}
+impl<T: Describe - ToString> From<T> for String {}
+impl<T: Display - Describe> From<T> for String {}
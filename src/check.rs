@@ -1,39 +1,79 @@
 pub mod checker;
+pub mod diagnostic;
+pub mod ir;
+pub mod names;
 pub mod parser;
+pub mod wf;
 
 use std::fmt::Display;
 
-#[derive(Debug, Clone)]
+/// 型式・トレイトの使用
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub struct TExp {
     name: String,
     params: Vec<TExp>,
+    /// トレイトの位置での `Item = u8` のような関連型の指定
+    assoc: Vec<(String, TExp)>,
+    /// `<T as Tr>::Name` の `T` と `Tr`。このとき `name` は関連型の名前
+    qself: Option<Box<(TExp, TExp)>>,
 }
-#[derive(Debug, Clone)]
+/// トレイトを原子とする論理式
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Formula<T> {
+    Atom(T),
+    Not(Box<Formula<T>>),
+    And(Vec<Formula<T>>),
+    Or(Vec<Formula<T>>),
+}
+/// 境界。`pos` を全て実装し、`neg` をどれも実装せず、`formulas` を全て満たす
+///
+/// `formulas` には `+`, `-` だけでは書けない部分が入る
+#[derive(Debug, Clone, Default)]
 pub struct Bound {
     pos: Vec<TExp>,
     neg: Vec<TExp>,
+    formulas: Vec<Formula<TExp>>,
 }
 #[derive(Debug, Clone)]
 pub struct Param {
     name: String,
     bound: Option<Bound>,
+    /// 構造体・トレイトの型変数の既定値
+    default: Option<TExp>,
 }
 #[derive(Debug, Clone)]
 pub struct Trait {
     name: String,
     params: Vec<Param>,
     supertraits: Option<Bound>,
+    predicates: Vec<Predicate>,
+    /// 関連型の名前
+    assoc_types: Vec<String>,
+}
+/// where 節の述語
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    /// `T == U`
+    Eq(TExp, TExp),
+    /// `T != U`
+    Ne(TExp, TExp),
+    /// `Vec<T>: A - B`
+    Bound(TExp, Bound),
 }
 #[derive(Debug, Clone)]
 pub struct Impl {
     params: Vec<Param>,
     trait_exp: TExp,
     impl_for: TExp,
+    predicates: Vec<Predicate>,
+    /// 関連型の定義
+    assoc: Vec<(String, TExp)>,
 }
 #[derive(Debug, Clone)]
 pub struct Struct {
     name: String,
     params: Option<Vec<Param>>,
+    predicates: Vec<Predicate>,
 }
 
 #[derive(Debug, Clone)]
@@ -48,31 +88,152 @@ pub struct Program(pub(crate) Vec<Decl>);
 
 impl Display for TExp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(q) = &self.qself {
+            return write!(f, "<{} as {}>::{}", q.0, q.1, self.name);
+        }
         write!(f, "{}", self.name)?;
-        let mut iter = self.params.iter();
-        if let Some(first) = iter.next() {
-            write!(f, "<{}", first)?;
-            for t in iter {
-                write!(f, ", {}", t)?;
-            }
-            write!(f, ">")?;
+        let params = self.params.iter().map(|t| t.to_string());
+        let assoc = self.assoc.iter().map(|(n, t)| format!("{} = {}", n, t));
+        let args: Vec<_> = params.chain(assoc).collect();
+        if !args.is_empty() {
+            write!(f, "<{}>", args.join(", "))?;
         }
         Ok(())
     }
 }
-impl Display for Bound {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut iter = self.pos.iter();
-        if let Some(first) = iter.next() {
-            write!(f, "{}", first)?;
-            for t in iter {
-                write!(f, " + {}", t)?;
+impl<T> Formula<T> {
+    pub fn try_map<U, E>(&self, f: &mut impl FnMut(&T) -> Result<U, E>) -> Result<Formula<U>, E> {
+        Ok(match self {
+            Formula::Atom(t) => Formula::Atom(f(t)?),
+            Formula::Not(g) => Formula::Not(Box::new(g.try_map(f)?)),
+            Formula::And(fs) => {
+                Formula::And(fs.iter().map(|g| g.try_map(f)).collect::<Result<_, _>>()?)
+            }
+            Formula::Or(fs) => {
+                Formula::Or(fs.iter().map(|g| g.try_map(f)).collect::<Result<_, _>>()?)
             }
+        })
+    }
+    pub fn map<U>(&self, f: &mut impl FnMut(&T) -> U) -> Formula<U> {
+        match self {
+            Formula::Atom(t) => Formula::Atom(f(t)),
+            Formula::Not(g) => Formula::Not(Box::new(g.map(f))),
+            Formula::And(fs) => Formula::And(fs.iter().map(|g| g.map(f)).collect()),
+            Formula::Or(fs) => Formula::Or(fs.iter().map(|g| g.map(f)).collect()),
         }
-        for t in self.neg.iter() {
-            write!(f, " - {}", t)?;
+    }
+    /// 論理式に現れる原子を全て集める
+    pub fn atoms<'a>(&'a self, res: &mut Vec<&'a T>) {
+        match self {
+            Formula::Atom(t) => res.push(t),
+            Formula::Not(g) => g.atoms(res),
+            Formula::And(fs) | Formula::Or(fs) => fs.iter().for_each(|g| g.atoms(res)),
         }
-        Ok(())
+    }
+    /// `+`, `-` で繋いだ項の並びとして見る。`bool` は項が否定されていないか
+    fn signed_terms(&self) -> Vec<(bool, &Formula<T>)> {
+        let items: Vec<_> = match self {
+            Formula::And(fs) => fs.iter().collect(),
+            _ => vec![self],
+        };
+        items
+            .into_iter()
+            .map(|g| match g {
+                Formula::Not(h) => (false, h.as_ref()),
+                _ => (true, g),
+            })
+            .collect()
+    }
+}
+impl Bound {
+    /// 項を一つ加える。`+`, `-` で書ける部分は `pos`, `neg` に入れる
+    fn push(&mut self, positive: bool, f: Formula<TExp>) {
+        match (positive, f) {
+            (true, Formula::Atom(t)) => self.pos.push(t),
+            (false, Formula::Atom(t)) => self.neg.push(t),
+            (positive, Formula::Not(g)) => self.push(!positive, *g),
+            (true, Formula::And(fs)) => fs.into_iter().for_each(|g| self.push(true, g)),
+            (true, f) => self.formulas.push(f),
+            (false, f) => self.formulas.push(Formula::Not(Box::new(f))),
+        }
+    }
+    /// 括弧の中身として、一つの論理式にまとめる
+    fn into_formula(self) -> Formula<TExp> {
+        let mut fs: Vec<_> = self.pos.into_iter().map(Formula::Atom).collect();
+        fs.extend(self.formulas);
+        fs.extend(
+            self.neg
+                .into_iter()
+                .map(|t| Formula::Not(Box::new(Formula::Atom(t)))),
+        );
+        if fs.len() == 1 {
+            fs.remove(0)
+        } else {
+            Formula::And(fs)
+        }
+    }
+}
+/// 項の並びを `A + B - C` の形で書く
+fn write_signed(
+    f: &mut std::fmt::Formatter<'_>,
+    items: impl IntoIterator<Item = (bool, String)>,
+) -> std::fmt::Result {
+    for (i, (positive, item)) in items.into_iter().enumerate() {
+        match (i, positive) {
+            (0, true) => write!(f, "{}", item)?,
+            (0, false) => write!(f, "-{}", item)?,
+            (_, true) => write!(f, " + {}", item)?,
+            (_, false) => write!(f, " - {}", item)?,
+        }
+    }
+    Ok(())
+}
+/// 境界を、正の境界、論理式、負の境界の順に書く
+fn write_bound<T: Display>(
+    f: &mut std::fmt::Formatter<'_>,
+    pos: &[String],
+    formulas: &[Formula<T>],
+    neg: &[String],
+) -> std::fmt::Result {
+    let pos = pos.iter().map(|t| (true, t.clone()));
+    let formulas = formulas.iter().flat_map(|g| {
+        g.signed_terms()
+            .into_iter()
+            .map(|(positive, h)| (positive, h.to_string()))
+    });
+    let neg = neg.iter().map(|t| (false, t.clone()));
+    write_signed(f, pos.chain(formulas).chain(neg))
+}
+/// 原子でなければ括弧で囲んで書く
+impl<T: Display> Display for Formula<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Formula::Atom(t) => write!(f, "{}", t),
+            Formula::Or(fs) => {
+                write!(f, "(")?;
+                for (i, g) in fs.iter().enumerate() {
+                    if 0 < i {
+                        write!(f, " | ")?;
+                    }
+                    let terms = g.signed_terms();
+                    write_signed(f, terms.into_iter().map(|(p, h)| (p, h.to_string())))?;
+                }
+                write!(f, ")")
+            }
+            Formula::Not(_) | Formula::And(_) => {
+                write!(f, "(")?;
+                let terms = self.signed_terms();
+                write_signed(f, terms.into_iter().map(|(p, h)| (p, h.to_string())))?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+impl Display for Bound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pos: Vec<_> = self.pos.iter().map(|t| t.to_string()).collect();
+        let neg: Vec<_> = self.neg.iter().map(|t| t.to_string()).collect();
+        write_bound(f, &pos, &self.formulas, &neg)
     }
 }
 impl Display for Param {
@@ -81,9 +242,32 @@ impl Display for Param {
         if let Some(b) = &self.bound {
             write!(f, ": {}", b)?;
         }
+        if let Some(d) = &self.default {
+            write!(f, " = {}", d)?;
+        }
         Ok(())
     }
 }
+impl Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Predicate::Eq(l, r) => write!(f, "{} == {}", l, r),
+            Predicate::Ne(l, r) => write!(f, "{} != {}", l, r),
+            Predicate::Bound(t, b) => write!(f, "{}: {}", t, b),
+        }
+    }
+}
+/// where 節を ` where P1, P2` の形で書く。述語が無ければ何も書かない
+fn write_where<T: Display>(f: &mut std::fmt::Formatter<'_>, predicates: &[T]) -> std::fmt::Result {
+    for (i, p) in predicates.iter().enumerate() {
+        if i == 0 {
+            write!(f, " where {}", p)?;
+        } else {
+            write!(f, ", {}", p)?;
+        }
+    }
+    Ok(())
+}
 impl Display for Impl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "impl")?;
@@ -95,7 +279,8 @@ impl Display for Impl {
             }
             write!(f, ">")?;
         }
-        write!(f, " {} for {}", self.trait_exp, self.impl_for)
+        write!(f, " {} for {}", self.trait_exp, self.impl_for)?;
+        write_where(f, &self.predicates)
     }
 }
 impl Display for Struct {
@@ -111,6 +296,69 @@ impl Display for Struct {
                 write!(f, ">")?;
             }
         }
+        write_where(f, &self.predicates)
+    }
+}
+impl Display for Trait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "trait {}", self.name)?;
+        let mut iter = self.params.iter();
+        if let Some(first) = iter.next() {
+            write!(f, "<{}", first)?;
+            for p in iter {
+                write!(f, ", {}", p)?;
+            }
+            write!(f, ">")?;
+        }
+        if let Some(b) = &self.supertraits {
+            write!(f, ": {}", b)?;
+        }
+        write_where(f, &self.predicates)
+    }
+}
+impl Display for Decl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decl::Struct(s) => write!(f, "struct {};", s),
+            Decl::Trait(t) if t.assoc_types.is_empty() => write!(f, "{} {{}}", t),
+            Decl::Trait(t) => {
+                write!(f, "{} {{", t)?;
+                for a in &t.assoc_types {
+                    write!(f, " type {};", a)?;
+                }
+                write!(f, " }}")
+            }
+            Decl::Impl(i) if i.assoc.is_empty() => write!(f, "{} {{}}", i),
+            Decl::Impl(i) => {
+                write!(f, "{} {{", i)?;
+                for (name, t) in &i.assoc {
+                    write!(f, " type {} = {};", name, t)?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for d in &self.0 {
+            writeln!(f, "{}", d)?;
+        }
         Ok(())
     }
 }
+
+/// 名前解決と整形式性の検査を続けて行う。誤りと警告は両方の検査のものを全て返す
+///
+/// 誤りが一つでもあれば IR は返さない
+pub fn lower(p: &Program) -> (Option<ir::Program>, Vec<String>) {
+    let (program, name_diagnostics) = names::lower(p);
+    let wf_errors = wf::check_program(&program);
+    let ok = wf_errors.is_empty() && !name_diagnostics.iter().any(|d| d.is_error());
+    let messages = name_diagnostics
+        .iter()
+        .map(|d| d.to_string())
+        .chain(wf_errors.iter().map(|e| e.to_string()))
+        .collect();
+    (ok.then_some(program), messages)
+}
@@ -1,16 +1,74 @@
+use super::diagnostic::Diagnostic;
+use super::ir::{
+    self, Bound, Impl, Param, Predicate, Struct, StructId, Trait, TraitId, TraitRef, Ty,
+};
 use super::*;
 use anyhow::{anyhow, Context as _, Result};
 use std::collections::{HashMap, HashSet};
 
+mod coverage;
+mod resolver;
+mod sat;
+pub use resolver::resolve;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConflictCheckResult {
     Conflict,
     NonConflict,
 }
 
-#[derive(Debug, Clone, Eq, Hash)]
+/// 検査の設定
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    /// 宣言された impl 以外に実装は存在しないとみなす
+    pub closed_world: bool,
+    /// A(b) に blanket impl の帰結を加える深さ。`None` なら加えない
+    pub blanket_depth: Option<usize>,
+    /// ヘッダに現れない impl の型変数を、境界を満たすものが存在すれば良い変数として扱う。
+    /// 閉世界では入り得る型を全て試す
+    pub existential: bool,
+}
+
+/// impl の探索を打ち切る深さ
+const IMPL_SEARCH_DEPTH: usize = 8;
+/// 存在する型変数に入れて試す型の組の上限
+const EXISTENTIAL_INSTANCES: usize = 64;
+
+/// 型がトレイトを実装しているかの判定結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Implements {
+    Yes,
+    No,
+    Unknown,
+}
+impl Implements {
+    pub fn not(self) -> Self {
+        match self {
+            Implements::Yes => Implements::No,
+            Implements::No => Implements::Yes,
+            Implements::Unknown => Implements::Unknown,
+        }
+    }
+    pub fn and(self, other: Self) -> Self {
+        match (self, other) {
+            (Implements::No, _) | (_, Implements::No) => Implements::No,
+            (Implements::Yes, Implements::Yes) => Implements::Yes,
+            _ => Implements::Unknown,
+        }
+    }
+    pub fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (Implements::Yes, _) | (_, Implements::Yes) => Implements::Yes,
+            (Implements::No, Implements::No) => Implements::No,
+            _ => Implements::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ConcreteType {
     Type {
+        id: StructId,
         name: String,
         params: Vec<ConcreteType>,
     },
@@ -19,40 +77,19 @@ pub enum ConcreteType {
         name: String,
     },
 }
-impl PartialEq for ConcreteType {
-    fn eq(&self, other: &Self) -> bool {
+impl ConcreteType {
+    /// 型式中に型変数 `id` が現れるか
+    pub fn contains_param(&self, id: usize) -> bool {
         match self {
-            Self::Type {
-                name: n1,
-                params: ps1,
-            } => match other {
-                Self::Type {
-                    name: n2,
-                    params: ps2,
-                } => {
-                    if n1 != n2 {
-                        return false;
-                    }
-                    for (p1, p2) in ps1.iter().zip(ps2.iter()) {
-                        if p1 != p2 {
-                            return false;
-                        }
-                    }
-                    true
-                }
-                Self::Param { .. } => false,
-            },
-            Self::Param { id: id1, .. } => match other {
-                Self::Type { .. } => false,
-                Self::Param { id: id2, .. } => id1 == id2,
-            },
+            ConcreteType::Type { params, .. } => params.iter().any(|p| p.contains_param(id)),
+            ConcreteType::Param { id: pid, .. } => *pid == id,
         }
     }
 }
 impl Display for ConcreteType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ConcreteType::Type { name, params } => {
+            ConcreteType::Type { name, params, .. } => {
                 write!(f, "{}", name)?;
                 let mut iter = params.iter();
                 if let Some(i) = iter.next() {
@@ -72,6 +109,7 @@ impl Display for ConcreteType {
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ConcreteTrait {
+    id: TraitId,
     name: String,
     params: Vec<ConcreteType>,
 }
@@ -89,66 +127,141 @@ impl Display for ConcreteTrait {
         Ok(())
     }
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ConcreteBound {
     pos: HashSet<ConcreteTrait>,
     neg: HashSet<ConcreteTrait>,
+    formulas: Vec<Formula<ConcreteTrait>>,
 }
 impl ConcreteBound {
     pub fn join(b: &ConcreteBound, c: &ConcreteBound) -> ConcreteBound {
         let b_pos = &b.pos;
         let c_pos = &c.pos;
-        let pos: HashSet<_> = b_pos.union(&c_pos).cloned().collect();
+        let pos: HashSet<_> = b_pos.union(c_pos).cloned().collect();
         let b_neg = &b.neg;
         let c_neg = &c.neg;
-        let neg: HashSet<_> = b_neg.union(&c_neg).cloned().collect();
-        let bound = ConcreteBound { pos, neg };
-        bound
+        let neg: HashSet<_> = b_neg.union(c_neg).cloned().collect();
+        let mut formulas = b.formulas.clone();
+        formulas.extend(
+            c.formulas
+                .iter()
+                .filter(|f| !b.formulas.contains(f))
+                .cloned(),
+        );
+        ConcreteBound { pos, neg, formulas }
     }
 }
 impl Display for ConcreteBound {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut pos = self.pos.iter();
-        if let Some(p) = pos.next() {
-            write!(f, "{}", p)?;
-            for p in pos {
-                write!(f, " + {}", p)?;
-            }
+        let mut pos: Vec<_> = self.pos.iter().map(|t| t.to_string()).collect();
+        let mut neg: Vec<_> = self.neg.iter().map(|t| t.to_string()).collect();
+        pos.sort();
+        neg.sort();
+        write_bound(f, &pos, &self.formulas, &neg)
+    }
+}
+
+/// 衝突する impl の組で、両方の境界を満たし得る経路
+#[derive(Debug, Clone)]
+pub struct Overlap {
+    param: String,
+    /// ヘッダに現れない型変数の経路
+    existential: bool,
+    ty: Option<ConcreteType>,
+    bound: ConcreteBound,
+    /// 論理式の境界を満たすトレイトの割り当ての例
+    witness: Option<ConcreteBound>,
+}
+impl Display for Overlap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.existential {
+            write!(f, "exists ")?;
+        }
+        write!(f, "{}", self.param)?;
+        if let Some(ty) = &self.ty {
+            write!(f, " = {}", ty)?;
         }
-        for n in self.neg.iter() {
-            write!(f, " - {}", n)?;
+        if !self.bound.pos.is_empty()
+            || !self.bound.neg.is_empty()
+            || !self.bound.formulas.is_empty()
+        {
+            write!(f, ": {}", self.bound)?;
+        }
+        if let Some(w) = &self.witness {
+            write!(f, ", e.g. {}: {}", self.param, w)?;
         }
         Ok(())
     }
 }
 
+/// 単一化に失敗した理由
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnificationError {
+    /// 型名・トレイト名が異なる
+    Mismatch(String, String),
+    /// 同名の型・トレイトでパラメータ数が異なる
+    Arity {
+        name: String,
+        left: usize,
+        right: usize,
+    },
+    /// 型変数がそれ自身を含む型式に束縛される
+    Occurs { param: String, ty: ConcreteType },
+    /// 等しくなってはならない型が等しくなる
+    Disequal(ConcreteType, ConcreteType),
+}
+impl Display for UnificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnificationError::Mismatch(n1, n2) => write!(f, "{} and {} mismatch", n1, n2),
+            UnificationError::Arity { name, left, right } => {
+                write!(f, "{} applied to {} and {} params", name, left, right)
+            }
+            UnificationError::Occurs { param, ty } => {
+                write!(f, "{} occurs in {}", param, ty)
+            }
+            UnificationError::Disequal(t1, t2) => write!(f, "{} and {} must differ", t1, t2),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum UnificationResult {
     Ok,
-    Failure,
+    Failure(UnificationError),
+}
+impl UnificationResult {
+    pub fn is_ok(&self) -> bool {
+        *self == UnificationResult::Ok
+    }
 }
 #[derive(Debug, Clone)]
-struct Unifier(HashMap<usize, ConcreteType>);
+struct Unifier {
+    bindings: HashMap<usize, ConcreteType>,
+    /// 束縛の結果、等しくなってはならない型の組
+    disequalities: Vec<(ConcreteType, ConcreteType)>,
+}
 impl Unifier {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self {
+            bindings: HashMap::new(),
+            disequalities: Vec::new(),
+        }
     }
-    pub fn resolve(&mut self, cty: &ConcreteType) -> ConcreteType {
+    pub fn resolve(&self, cty: &ConcreteType) -> ConcreteType {
         match cty {
-            ConcreteType::Type { name, params } => {
+            ConcreteType::Type { id, name, params } => {
                 let params = params.iter().map(|p| self.resolve(p)).collect();
                 ConcreteType::Type {
+                    id: *id,
                     name: name.clone(),
                     params,
                 }
             }
             ConcreteType::Param { id, .. } => {
-                let sct = self.0.get(id).cloned();
-                if let Some(ct) = sct {
-                    let new_ct = self.resolve(&ct);
-                    // あとでトレイト境界を集める時に経路を辿れるようにする
-                    //self.0.insert(*id, new_ct.clone());
-                    new_ct
+                if let Some(ct) = self.bindings.get(id) {
+                    // あとでトレイト境界を集める時に経路を辿れるよう、束縛は書き換えない
+                    self.resolve(ct)
                 } else {
                     cty.clone()
                 }
@@ -156,366 +269,2903 @@ impl Unifier {
         }
     }
 
+    pub fn resolve_trait(&self, ctr: &ConcreteTrait) -> ConcreteTrait {
+        ConcreteTrait {
+            id: ctr.id,
+            name: ctr.name.clone(),
+            params: ctr.params.iter().map(|p| self.resolve(p)).collect(),
+        }
+    }
+
+    pub fn resolve_bound(&self, b: &ConcreteBound) -> ConcreteBound {
+        ConcreteBound {
+            pos: b.pos.iter().map(|t| self.resolve_trait(t)).collect(),
+            neg: b.neg.iter().map(|t| self.resolve_trait(t)).collect(),
+            formulas: b
+                .formulas
+                .iter()
+                .map(|f| f.map(&mut |t| self.resolve_trait(t)))
+                .collect(),
+        }
+    }
+
+    /// 型変数 `id` を `cty` に束縛する
+    ///
+    /// `cty` は解決済みであること
+    fn bind(&mut self, id: usize, name: &str, cty: &ConcreteType) -> UnificationResult {
+        if let ConcreteType::Param { id: id2, .. } = cty {
+            if *id2 == id {
+                return UnificationResult::Ok;
+            }
+        }
+        if cty.contains_param(id) {
+            return UnificationResult::Failure(UnificationError::Occurs {
+                param: name.to_string(),
+                ty: cty.clone(),
+            });
+        }
+        self.bindings.insert(id, cty.clone());
+        self.check_disequalities()
+    }
+    /// 解決すると等しくなる不等式があれば失敗とする
+    fn check_disequalities(&self) -> UnificationResult {
+        for (l, r) in &self.disequalities {
+            let (l, r) = (self.resolve(l), self.resolve(r));
+            if l == r {
+                return UnificationResult::Failure(UnificationError::Disequal(l, r));
+            }
+        }
+        UnificationResult::Ok
+    }
+    /// `cty1` と `cty2` が等しくならないという条件を加える
+    ///
+    /// 以降の束縛で等しくなった時点で単一化は失敗する
+    pub fn type_disunify(&mut self, cty1: &ConcreteType, cty2: &ConcreteType) -> UnificationResult {
+        self.disequalities.push((cty1.clone(), cty2.clone()));
+        self.check_disequalities()
+    }
+    fn params_unify(
+        &mut self,
+        name: &str,
+        ps1: &[ConcreteType],
+        ps2: &[ConcreteType],
+    ) -> UnificationResult {
+        if ps1.len() != ps2.len() {
+            return UnificationResult::Failure(UnificationError::Arity {
+                name: name.to_string(),
+                left: ps1.len(),
+                right: ps2.len(),
+            });
+        }
+        for (p1, p2) in ps1.iter().zip(ps2.iter()) {
+            let res = self.type_unify(p1, p2);
+            if !res.is_ok() {
+                return res;
+            }
+        }
+        UnificationResult::Ok
+    }
+
     pub fn type_unify(&mut self, cty1: &ConcreteType, cty2: &ConcreteType) -> UnificationResult {
         let cty1 = self.resolve(cty1);
         let cty2 = self.resolve(cty2);
         match &cty1 {
             ConcreteType::Type {
+                id: id1,
                 name: n1,
                 params: ps1,
             } => match &cty2 {
                 ConcreteType::Type {
+                    id: id2,
                     name: n2,
                     params: ps2,
                 } => {
-                    if n1 != n2 {
-                        return UnificationResult::Failure;
-                    }
-                    for (p1, p2) in ps1.iter().zip(ps2.iter()) {
-                        if self.type_unify(p1, p2) == UnificationResult::Failure {
-                            return UnificationResult::Failure;
-                        }
+                    if id1 != id2 {
+                        return UnificationResult::Failure(UnificationError::Mismatch(
+                            n1.clone(),
+                            n2.clone(),
+                        ));
                     }
-                    UnificationResult::Ok
-                }
-                ConcreteType::Param { id, .. } => {
-                    self.0.insert(*id, cty1.clone());
-                    UnificationResult::Ok
-                }
-            },
-            ConcreteType::Param { id: id1, .. } => match cty2 {
-                ConcreteType::Type { .. } => self.type_unify(&cty2, &cty1),
-                ConcreteType::Param { .. } => {
-                    self.0.insert(*id1, cty2.clone());
-                    UnificationResult::Ok
+                    self.params_unify(n1, ps1, ps2)
                 }
+                ConcreteType::Param { id, name } => self.bind(*id, name, &cty1),
             },
+            ConcreteType::Param { id: id1, name } => self.bind(*id1, name, &cty2),
         }
     }
     pub fn trait_unify(&mut self, ctr1: &ConcreteTrait, ctr2: &ConcreteTrait) -> UnificationResult {
-        if ctr1.name != ctr2.name {
-            UnificationResult::Failure
+        if ctr1.id != ctr2.id {
+            UnificationResult::Failure(UnificationError::Mismatch(
+                ctr1.name.clone(),
+                ctr2.name.clone(),
+            ))
         } else {
-            for (p1, p2) in ctr1.params.iter().zip(ctr2.params.iter()) {
-                if self.type_unify(p1, p2) == UnificationResult::Failure {
-                    return UnificationResult::Failure;
-                }
-            }
-            UnificationResult::Ok
+            self.params_unify(&ctr1.name, &ctr1.params, &ctr2.params)
         }
     }
 
-    fn get_routes(&self, routes: &mut Vec<(Option<ConcreteType>, HashSet<usize>)>, i: usize) {
-        let mut route = None;
-        for r in routes.iter_mut() {
-            if r.1.contains(&i) {
-                route = Some(r)
+    /// `pat` 側の型変数だけを束縛して `target` に一致させる (一方向の単一化)
+    pub fn type_match(&mut self, pat: &ConcreteType, target: &ConcreteType) -> UnificationResult {
+        match pat {
+            ConcreteType::Param { id, .. } => {
+                if let Some(ct) = self.bindings.get(id) {
+                    let ct = self.resolve(ct);
+                    if ct == *target {
+                        UnificationResult::Ok
+                    } else {
+                        UnificationResult::Failure(UnificationError::Mismatch(
+                            ct.to_string(),
+                            target.to_string(),
+                        ))
+                    }
+                } else {
+                    self.bindings.insert(*id, target.clone());
+                    self.check_disequalities()
+                }
             }
-        }
-        if route.is_none() {
-            let last = routes.len();
-            routes.push((None, HashSet::from([i])));
-            route = routes.get_mut(last);
-        }
-        let route = route.unwrap();
-        if let Some(ct) = self.0.get(&i) {
-            match ct {
-                ConcreteType::Param { id, .. } => {
-                    route.1.insert(*id);
-                    self.get_routes(routes, *id);
+            ConcreteType::Type {
+                id: id1,
+                name: n1,
+                params: ps1,
+            } => match target {
+                ConcreteType::Type {
+                    id: id2,
+                    name: n2,
+                    params: ps2,
+                } => {
+                    if id1 != id2 {
+                        return UnificationResult::Failure(UnificationError::Mismatch(
+                            n1.clone(),
+                            n2.clone(),
+                        ));
+                    }
+                    self.params_match(n1, ps1, ps2)
                 }
-                ConcreteType::Type { .. } => {
-                    route.0 = Some(ct.clone());
+                ConcreteType::Param { name, .. } => {
+                    UnificationResult::Failure(UnificationError::Mismatch(n1.clone(), name.clone()))
                 }
-            }
+            },
+        }
+    }
+    pub fn trait_match(
+        &mut self,
+        pat: &ConcreteTrait,
+        target: &ConcreteTrait,
+    ) -> UnificationResult {
+        if pat.id != target.id {
+            UnificationResult::Failure(UnificationError::Mismatch(
+                pat.name.clone(),
+                target.name.clone(),
+            ))
         } else {
-            route.1.insert(i);
+            self.params_match(&pat.name, &pat.params, &target.params)
         }
     }
-    pub fn get_all_unified_params(&self) -> Vec<(Option<ConcreteType>, HashSet<usize>)> {
-        let mut routes = Vec::new();
-        for i in self.0.keys() {
-            self.get_routes(&mut routes, *i);
+    fn params_match(
+        &mut self,
+        name: &str,
+        ps1: &[ConcreteType],
+        ps2: &[ConcreteType],
+    ) -> UnificationResult {
+        if ps1.len() != ps2.len() {
+            return UnificationResult::Failure(UnificationError::Arity {
+                name: name.to_string(),
+                left: ps1.len(),
+                right: ps2.len(),
+            });
         }
-        routes
+        for (p1, p2) in ps1.iter().zip(ps2.iter()) {
+            let res = self.type_match(p1, p2);
+            if !res.is_ok() {
+                return res;
+            }
+        }
+        UnificationResult::Ok
+    }
+
+    /// 型変数同士の束縛だけを辿って代表の型変数を求める
+    pub fn root(&self, mut id: usize) -> usize {
+        while let Some(ConcreteType::Param { id: next, .. }) = self.bindings.get(&id) {
+            id = *next;
+        }
+        id
+    }
+    /// 代表が `root` である型変数の集合
+    pub fn class_of(&self, root: usize) -> HashSet<usize> {
+        let mut class = HashSet::from([root]);
+        class.extend(self.bindings.keys().filter(|i| self.root(**i) == root));
+        class
+    }
+    /// 単一化された型変数の経路を全て集める
+    ///
+    /// 型式に束縛された経路はその型式 (解決済み) を持つ。
+    /// 型式の内側の型変数の境界は、型式が境界を満たすかを impl から調べる際に参照される
+    pub fn get_all_unified_params(&self) -> Vec<(Option<ConcreteType>, HashSet<usize>)> {
+        let mut roots: Vec<_> = self.bindings.keys().map(|i| self.root(*i)).collect();
+        roots.sort();
+        roots.dedup();
+        roots
+            .into_iter()
+            .map(|r| {
+                let ct = self.bindings.get(&r).map(|ct| self.resolve(ct));
+                (ct, self.class_of(r))
+            })
+            .collect()
     }
 }
 
+/// 射影 `<self_ty as trait_ref>::name`。その値を型変数 `value` で表す
+#[derive(Debug, Clone)]
+struct Projection {
+    self_ty: ConcreteType,
+    trait_ref: ConcreteTrait,
+    name: String,
+    value: ConcreteType,
+}
 struct ConcreteImpl {
     trait_exp: ConcreteTrait,
     impl_for: ConcreteType,
+    /// where 節の `==` の組
+    equalities: Vec<(ConcreteType, ConcreteType)>,
+    /// where 節の `!=` の組
+    disequalities: Vec<(ConcreteType, ConcreteType)>,
+    /// where 節の、型変数でない型への境界
+    type_bounds: Vec<(ConcreteType, ConcreteBound)>,
+    /// impl に現れる射影。関連型の指定は、射影の型変数についての等式として `equalities` に入る
+    projections: Vec<Projection>,
+    /// `type Item = u8;` のような関連型の定義
+    assoc: Vec<(String, ConcreteType)>,
+}
+impl ConcreteImpl {
+    /// 型変数への境界以外の where 節の述語か、射影を持つか
+    fn has_predicates(&self) -> bool {
+        !self.equalities.is_empty()
+            || !self.disequalities.is_empty()
+            || !self.type_bounds.is_empty()
+            || !self.projections.is_empty()
+    }
+}
+/// 型変数に宣言された境界から課される条件 `ty: tr`
+struct Obligation {
+    ty: ConcreteType,
+    /// `ty` が満たすべき論理式。負の境界は否定した原子になる
+    bound: Formula<ConcreteTrait>,
+    /// 条件を課す構造体・トレイトの使用
+    by: String,
 }
 struct ConflictCheckEnv<'a> {
     checker: &'a Checker,
     params: Vec<Option<ConcreteBound>>,
+    param_names: Vec<String>,
+    /// 検査対象の impl の型変数の数。これ以降は impl の探索で導入された型変数
+    checked_params: usize,
+    /// blanket impl とその型変数
+    blankets: Vec<(ConcreteImpl, std::ops::Range<usize>)>,
+    /// impl の探索中の目標
+    goals: Vec<(ConcreteType, ConcreteTrait)>,
+    /// 具体化中の impl に現れた射影。射影の型式はこの中から探す
+    projections: Vec<Projection>,
+    /// 構造体の定義から導かれる境界を型変数の境界に加えるか
+    implied_bounds: bool,
 }
 impl<'a> ConflictCheckEnv<'a> {
     pub fn new(checker: &'a Checker) -> Self {
         Self {
             checker,
             params: Vec::new(),
+            param_names: Vec::new(),
+            checked_params: 0,
+            blankets: Vec::new(),
+            goals: Vec::new(),
+            projections: Vec::new(),
+            implied_bounds: true,
         }
     }
-    fn texp_to_concrete_type(
-        &self,
-        env: &HashMap<String, usize>,
-        te: &TExp,
-    ) -> Result<ConcreteType> {
-        if let Some(id) = env.get(&te.name) {
-            Ok(ConcreteType::Param {
+    /// 型変数 `id` を表す具体型
+    fn param_ty(&self, id: usize) -> ConcreteType {
+        ConcreteType::Param {
+            id,
+            name: self.param_names[id].clone(),
+        }
+    }
+    /// 型式を具体型に変換する
+    ///
+    /// 型変数は `args` の同じ位置にあるものに置き換える
+    fn lower_type(&self, args: &[ConcreteType], ty: &Ty) -> Result<ConcreteType> {
+        match ty {
+            Ty::Param { id, name } => args
+                .get(id.0)
+                .cloned()
+                .ok_or_else(|| anyhow!("Type parameter {} not bound", name)),
+            Ty::Struct { id, name, params } => Ok(ConcreteType::Type {
                 id: *id,
-                name: te.name.clone(),
-            })
-        } else {
-            if let Some(st) = self.checker.structs.get(&te.name) {
-                let te_param_len = te.params.len();
-                if st.params.as_ref().map(|ps| ps.len()).unwrap_or(0) != te_param_len {
-                    Err(anyhow!("Param length error between {} and {}", st, te))
-                } else {
-                    let params = te
-                        .params
-                        .iter()
-                        .map(|p| self.texp_to_concrete_type(env, p))
-                        .collect::<Result<Vec<_>, _>>()?;
-                    Ok(ConcreteType::Type {
-                        name: te.name.clone(),
-                        params,
-                    })
-                }
-            } else {
-                Err(anyhow!("Undefined struct {}", te.name))
+                name: name.clone(),
+                params: params
+                    .iter()
+                    .map(|p| self.lower_type(args, p))
+                    .collect::<Result<Vec<_>, _>>()?,
+            }),
+            // 射影は impl の具体化で型変数として確保してある
+            Ty::Projection {
+                self_ty,
+                trait_ref,
+                name,
+            } => {
+                let self_ty = self.lower_type(args, self_ty)?;
+                let trait_ref = self.lower_trait(args, trait_ref)?;
+                self.projections
+                    .iter()
+                    .find(|p| p.self_ty == self_ty && p.trait_ref == trait_ref && p.name == *name)
+                    .map(|p| p.value.clone())
+                    .ok_or_else(|| anyhow!("Projection {} is only supported in impls", ty))
             }
+            Ty::Error(name) => Err(anyhow!("Unresolved type {}", name)),
         }
     }
-    fn texp_to_concrete_trait(
-        &self,
-        env: &HashMap<String, usize>,
-        te: &TExp,
-    ) -> Result<ConcreteTrait> {
-        let params = te
+    /// 射影を表す型変数。まだ無ければ確保する
+    fn projection(
+        &mut self,
+        self_ty: ConcreteType,
+        trait_ref: ConcreteTrait,
+        name: &str,
+    ) -> ConcreteType {
+        if let Some(p) = self
+            .projections
+            .iter()
+            .find(|p| p.self_ty == self_ty && p.trait_ref == trait_ref && p.name == name)
+        {
+            return p.value.clone();
+        }
+        let id = self.params.len();
+        self.param_names
+            .push(format!("<{} as {}>::{}", self_ty, trait_ref, name));
+        self.params.push(None);
+        let value = self.param_ty(id);
+        self.projections.push(Projection {
+            self_ty,
+            trait_ref,
+            name: name.to_string(),
+            value: value.clone(),
+        });
+        value
+    }
+    /// 境界中の `Item = u8` のような関連型の指定を、`ty` の射影についての等式にする
+    fn assoc_equalities(
+        &mut self,
+        args: &[ConcreteType],
+        ty: &ConcreteType,
+        b: &Bound,
+        res: &mut Vec<(ConcreteType, ConcreteType)>,
+    ) -> Result<()> {
+        for tr in b.pos.iter().filter(|t| !t.assoc.is_empty()) {
+            let ct = self.lower_trait(args, tr)?;
+            for (name, t) in &tr.assoc {
+                let proj = self.projection(ty.clone(), ct.clone(), name);
+                res.push((proj, self.lower_type(args, t)?));
+            }
+        }
+        Ok(())
+    }
+    fn lower_trait(&self, args: &[ConcreteType], tr: &TraitRef) -> Result<ConcreteTrait> {
+        let params = tr
             .params
             .iter()
-            .map(|t| self.texp_to_concrete_type(env, t))
+            .map(|p| self.lower_type(args, p))
             .collect::<Result<Vec<_>, _>>()?;
         Ok(ConcreteTrait {
-            name: te.name.clone(),
+            id: tr.id,
+            name: tr.name.clone(),
             params,
         })
     }
+    fn lower_bound(&self, args: &[ConcreteType], b: &Bound) -> Result<ConcreteBound> {
+        Ok(ConcreteBound {
+            pos: b
+                .pos
+                .iter()
+                .map(|t| self.lower_trait(args, t))
+                .collect::<Result<_>>()?,
+            neg: b
+                .neg
+                .iter()
+                .map(|t| self.lower_trait(args, t))
+                .collect::<Result<_>>()?,
+            formulas: b
+                .formulas
+                .iter()
+                .map(|f| f.try_map(&mut |t| self.lower_trait(args, t)))
+                .collect::<Result<_>>()?,
+        })
+    }
 
-    /// 論文中 Sup に相当
-    pub fn sup(&self, ct: &ConcreteTrait) -> Result<Vec<ConcreteTrait>> {
-        if let Some(Trait {
-            params,
-            supertraits,
-            ..
-        }) = self.checker.traits.get(&ct.name)
-        {
-            let mut env = HashMap::new();
-            for (n, t) in params.iter().map(|p| &p.name).zip(ct.params.iter()) {
-                env.insert(n.clone(), t.clone());
+    /// トレイトの宣言中の型変数に与える引数。`Self` は最後の型変数で、`ty` に置き換える
+    fn trait_args(ty: &ConcreteType, ct: &ConcreteTrait) -> Vec<ConcreteType> {
+        let mut args = ct.params.clone();
+        args.push(ty.clone());
+        args
+    }
+    /// 論文中 Sup に相当。`ty` は `ct` を実装する型
+    pub fn sup(&self, ty: &ConcreteType, ct: &ConcreteTrait) -> Result<Vec<ConcreteTrait>> {
+        match &self.checker.traits[ct.id.0].supertraits {
+            Some(b) => {
+                let args = Self::trait_args(ty, ct);
+                b.pos.iter().map(|t| self.lower_trait(&args, t)).collect()
             }
-            if let Some(supertraits) = supertraits {
-                let supertraits = supertraits
-                    .pos
-                    .iter()
-                    .map(|t| {
-                        let params = t
-                            .params
-                            .iter()
-                            .map(|p| {
-                                if let Some(ct) = env.get(&p.name) {
-                                    Ok(ct.clone())
-                                } else {
-                                    self.texp_to_concrete_type(&HashMap::new(), p)
-                                }
-                            })
-                            .collect::<Result<Vec<_>, _>>()?;
-                        anyhow::Ok(ConcreteTrait {
-                            name: t.name.clone(),
-                            params,
-                        })
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                Ok(supertraits)
-            } else {
-                Ok(Vec::new())
+            None => Ok(Vec::new()),
+        }
+    }
+    /// 負の上位トレイト。`ct` を実装する型はこれらを実装しない
+    pub fn excluded_by(&self, ty: &ConcreteType, ct: &ConcreteTrait) -> Result<Vec<ConcreteTrait>> {
+        match &self.checker.traits[ct.id.0].supertraits {
+            Some(b) => {
+                let args = Self::trait_args(ty, ct);
+                b.neg.iter().map(|t| self.lower_trait(&args, t)).collect()
             }
-        } else {
-            Err(anyhow!("Trait {} not declared", ct.name))
+            None => Ok(Vec::new()),
         }
     }
     /// 論文中 A' に相当
-    pub fn a_d(&self, ct: &ConcreteTrait) -> Result<HashSet<ConcreteTrait>> {
-        let mut res = HashSet::from([ct.clone()]);
-        let sups = self
-            .sup(ct)?
-            .iter()
-            .map(|t| self.sup(t))
-            .collect::<Result<HashSet<_>, _>>()?
-            .into_iter()
-            .flatten()
-            .collect::<HashSet<_>>();
-        res.extend(sups);
+    ///
+    /// 上位トレイトを不動点まで辿る。循環している辺は辿らない
+    pub fn a_d(&self, ty: &ConcreteType, ct: &ConcreteTrait) -> Result<HashSet<ConcreteTrait>> {
+        let mut res = HashSet::new();
+        self.collect_supertraits(ty, ct, &mut Vec::new(), &mut res)?;
         Ok(res)
     }
-    /// 論文中 A に相当
-    pub fn a(&self, b: &ConcreteBound) -> Result<HashSet<ConcreteTrait>> {
-        let res = b
+    fn collect_supertraits(
+        &self,
+        ty: &ConcreteType,
+        ct: &ConcreteTrait,
+        path: &mut Vec<TraitId>,
+        res: &mut HashSet<ConcreteTrait>,
+    ) -> Result<()> {
+        // 循環は Checker::supertrait_cycles で報告済み
+        if path.contains(&ct.id) || !res.insert(ct.clone()) {
+            return Ok(());
+        }
+        path.push(ct.id);
+        for t in self.sup(ty, ct)? {
+            self.collect_supertraits(ty, &t, path, res)?;
+        }
+        path.pop();
+        Ok(())
+    }
+    /// 論文中 A に相当。`ty` は境界 `b` を満たす型
+    ///
+    /// `CheckOptions::blanket_depth` が指定されていれば blanket impl の帰結も加える
+    pub fn a(&self, ty: &ConcreteType, b: &ConcreteBound) -> Result<HashSet<ConcreteTrait>> {
+        let mut res = b
             .pos
             .iter()
-            .map(|t| self.a_d(t))
+            .map(|t| self.a_d(ty, t))
             .collect::<Result<Vec<_>, _>>()?
             .into_iter()
             .flatten()
             .collect();
+        if let Some(depth) = self.checker.options.blanket_depth {
+            self.saturate_with_blankets(ty, &mut res, b, depth)?;
+        }
         Ok(res)
     }
-
-    /// Check trait bounds
-    fn check_bound(&self, b: &ConcreteBound) -> Result<ConflictCheckResult> {
-        let a = self.a(b).with_context(|| format!("A({}) error", b))?;
-        if 0 < a.intersection(&b.neg).count() {
-            Ok(ConflictCheckResult::NonConflict)
-        } else {
-            Ok(ConflictCheckResult::Conflict)
+    /// A(b) に含まれるトレイトが負の上位トレイトとして除外するトレイト
+    pub fn excluded(
+        &self,
+        ty: &ConcreteType,
+        a: &HashSet<ConcreteTrait>,
+    ) -> Result<HashSet<ConcreteTrait>> {
+        let mut res = HashSet::new();
+        for t in a {
+            res.extend(self.excluded_by(ty, t)?);
         }
+        Ok(res)
     }
-
-    pub fn get_concrete_impl(&mut self, im: &Impl) -> Result<ConcreteImpl> {
-        let mut env = HashMap::new();
-        for p in &im.params {
-            let param_id = self.params.len();
-            if let Some(bound) = &p.bound {
-                let pos = bound
-                    .pos
-                    .iter()
-                    .map(|t| self.texp_to_concrete_trait(&env, t))
-                    .collect::<Result<HashSet<_>, _>>()?;
-                let neg = bound
-                    .neg
-                    .iter()
-                    .map(|t| self.texp_to_concrete_trait(&env, t))
-                    .collect::<Result<HashSet<_>, _>>()?;
-                let b = ConcreteBound { pos, neg };
-                self.params.push(Some(b));
-            } else {
-                self.params.push(None);
-            }
-            env.insert(p.name.clone(), param_id);
+    /// 境界を満たす型が存在しないか
+    ///
+    /// A(b) が負の境界か、A(b) 自身が除外するトレイトを含めば満たせない。
+    /// 論理式を含む場合は割り当てを探す
+    pub fn unsatisfiable(&self, ty: &ConcreteType, b: &ConcreteBound) -> Result<bool> {
+        let a = self.a(ty, b)?;
+        let excluded = self.excluded(ty, &a)?;
+        if a.iter().any(|t| b.neg.contains(t) || excluded.contains(t)) {
+            return Ok(true);
         }
-        let trait_params = im
-            .trait_exp
-            .params
-            .iter()
-            .map(|p| self.texp_to_concrete_type(&env, p))
-            .collect::<Result<Vec<_>, _>>()?;
-        let trait_exp = ConcreteTrait {
-            name: im.trait_exp.name.clone(),
-            params: trait_params,
-        };
-        let impl_for = self.texp_to_concrete_type(&env, &im.impl_for)?;
-        let cimpl = ConcreteImpl {
-            trait_exp,
-            impl_for,
-        };
-        Ok(cimpl)
+        // 探索が上限に達した場合は満たせるとみなす
+        Ok(self.needs_sat(ty, b)? && matches!(self.satisfy(ty, b)?, sat::Satisfy::No))
     }
-
-    fn check_impls<'b>(checker: &'a Checker, i1: &Impl, i2: &Impl) -> Result<ConflictCheckResult> {
-        let mut env = Self::new(checker);
-        let c1 = env.get_concrete_impl(i1)?;
-        let c2 = env.get_concrete_impl(i2)?;
-        let mut unif = Unifier::new();
-        if unif.trait_unify(&c1.trait_exp, &c2.trait_exp) == UnificationResult::Ok {
-            if unif.type_unify(&c1.impl_for, &c2.impl_for) == UnificationResult::Ok {
-                let params = unif.get_all_unified_params();
-                for (ct, ps) in params {
-                    let mut bound = ConcreteBound {
-                        pos: HashSet::new(),
-                        neg: HashSet::new(),
-                    };
-                    for p in ps.iter() {
-                        let b = &env.params[*p];
-                        if let Some(b) = b {
-                            bound = ConcreteBound::join(&bound, b);
-                        } else {
-                            return Ok(ConflictCheckResult::Conflict);
-                        }
-                    }
-                    if let Some(_) = ct {
-                        // 将来課題：現状では型変数と型式が単一化された段階で衝突
-                        // orphan rule などを考慮しながら、どうするか考える
-                        return Ok(ConflictCheckResult::Conflict);
-                    } else {
-                        if env
-                            .check_bound(&bound)
-                            .with_context(|| format!("Bound {} check error", bound))?
-                            == ConflictCheckResult::NonConflict
-                        {
-                            return Ok(ConflictCheckResult::NonConflict);
-                        }
-                    }
+    /// 報告用に境界を最小化する
+    ///
+    /// 他の正の境界の A' に含まれる正の境界と、より強い負の境界に含意される負の境界を除く
+    pub fn normalize(&self, ty: &ConcreteType, b: &ConcreteBound) -> Result<ConcreteBound> {
+        fn sorted(ts: &HashSet<ConcreteTrait>) -> Vec<ConcreteTrait> {
+            let mut ts: Vec<_> = ts.iter().cloned().collect();
+            ts.sort_by_key(|t| t.to_string());
+            ts
+        }
+        let mut pos = b.pos.clone();
+        for t in sorted(&b.pos) {
+            let mut implied = false;
+            for u in pos.iter().filter(|u| **u != t) {
+                if self.a_d(ty, u)?.contains(&t) {
+                    implied = true;
+                    break;
                 }
-                return Ok(ConflictCheckResult::Conflict);
+            }
+            if implied {
+                pos.remove(&t);
             }
         }
-        Ok(ConflictCheckResult::NonConflict)
-    }
-}
-
-/// 検査を行うやつ
-pub struct Checker {
-    structs: HashMap<String, Struct>,
-    traits: HashMap<String, Trait>,
-    impls: Vec<Impl>,
-}
-
-impl Checker {
-    pub fn new() -> Self {
-        Self {
-            structs: HashMap::new(),
-            traits: HashMap::new(),
-            impls: Vec::new(),
+        // Super を除けば Sub も除かれる
+        let mut neg = b.neg.clone();
+        for t in sorted(&b.neg) {
+            let a_t = self.a_d(ty, &t)?;
+            if neg.iter().any(|u| *u != t && a_t.contains(u)) {
+                neg.remove(&t);
+            }
         }
+        Ok(ConcreteBound {
+            pos,
+            neg,
+            formulas: b.formulas.clone(),
+        })
     }
 
-    pub fn insert(&mut self, Program(p): Program) -> Result<()> {
-        for d in p {
-            match d {
-                Decl::Struct(s) => {
-                    self.structs.insert(s.name.clone(), s);
+    /// `impl<T: ..> Tr for T` の形の impl を、境界が満たされる限り `depth` 回まで適用する
+    fn saturate_with_blankets(
+        &self,
+        ty: &ConcreteType,
+        res: &mut HashSet<ConcreteTrait>,
+        b: &ConcreteBound,
+        depth: usize,
+    ) -> Result<()> {
+        for _ in 0..depth {
+            let mut added = Vec::new();
+            for (blanket, params) in &self.blankets {
+                let ConcreteType::Param { id: self_id, .. } = blanket.impl_for else {
+                    continue;
+                };
+                // 自身以外の型変数の境界は検証できないので適用しない
+                if params
+                    .clone()
+                    .any(|p| p != self_id && self.params[p].is_some())
+                {
+                    continue;
+                }
+                let empty = ConcreteBound::default();
+                let self_bound = self.params[self_id].as_ref().unwrap_or(&empty);
+                // 論理式の境界と where 節は A(b) の要素との照合では確かめられない
+                if !self_bound.formulas.is_empty() || blanket.has_predicates() {
+                    continue;
+                }
+                // blanket impl の実装先の型変数は `ty` に束縛する
+                let mut unif = Unifier::new();
+                unif.type_match(&blanket.impl_for, ty);
+                let matched = self_bound.pos.iter().all(|t| {
+                    res.iter().any(|r| {
+                        let mut u = unif.clone();
+                        if u.trait_match(t, r).is_ok() {
+                            unif = u;
+                            true
+                        } else {
+                            false
+                        }
+                    })
+                });
+                if !matched {
+                    continue;
                 }
-                Decl::Trait(t) => {
-                    self.traits.insert(t.name.clone(), t);
+                // 負の境界は、b の負の境界か負の上位トレイトにより実装されないと分かる場合のみ満たされる
+                let res_excluded = self.excluded(ty, res)?;
+                let mut excluded = true;
+                for t in &self_bound.neg {
+                    let t = unif.resolve_trait(t);
+                    let a_t = self.a_d(ty, &t)?;
+                    if !b.neg.iter().chain(&res_excluded).any(|n| a_t.contains(n)) {
+                        excluded = false;
+                    }
                 }
-                Decl::Impl(i) => {
-                    self.impls.push(i);
+                let t = unif.resolve_trait(&blanket.trait_exp);
+                let determined = t
+                    .params
+                    .iter()
+                    .all(|ct| params.clone().all(|p| !ct.contains_param(p)));
+                if excluded && determined && !res.contains(&t) {
+                    added.push(t);
                 }
             }
+            if added.is_empty() {
+                break;
+            }
+            for t in added {
+                res.extend(self.a_d(ty, &t)?);
+            }
+        }
+        Ok(())
+    }
+    /// blanket impl を具体化して保持する
+    fn lower_blankets(&mut self) -> Result<()> {
+        let checker = self.checker;
+        for im in &checker.impls {
+            if matches!(im.impl_for, Ty::Param { .. }) {
+                let base = self.params.len();
+                let ci = self.get_concrete_impl(im)?;
+                self.blankets.push((ci, base..self.params.len()));
+            }
         }
         Ok(())
     }
 
-    fn check_impls(&self, i1: &Impl, i2: &Impl) -> Result<ConflictCheckResult> {
-        ConflictCheckEnv::check_impls(self, i1, i2)
+    /// Check trait bounds
+    ///
+    /// 単一化の結果を適用してから A(b) と負の境界を比べる
+    fn check_bound(
+        &self,
+        unif: &Unifier,
+        ty: &ConcreteType,
+        b: &ConcreteBound,
+    ) -> Result<ConflictCheckResult> {
+        let b = unif.resolve_bound(b);
+        if self
+            .unsatisfiable(&unif.resolve(ty), &b)
+            .with_context(|| format!("A({}) error", b))?
+        {
+            Ok(ConflictCheckResult::NonConflict)
+        } else {
+            Ok(ConflictCheckResult::Conflict)
+        }
     }
 
-    pub fn check(&mut self, p: Program) -> Result<Vec<(ConflictCheckResult, Impl, Impl)>> {
-        let mut res = Vec::new();
-        self.insert(p)?;
-        for i in 0..self.impls.len() {
-            for j in (i + 1)..self.impls.len() {
-                let i1 = self.impls[i].clone();
-                let i2 = self.impls[j].clone();
-                res.push((
-                    self.check_impls(&i1, &i2)
-                        .with_context(|| format!("implementation {}, {} check error", i1, i2))?,
-                    i1,
-                    i2,
-                ));
-            }
+    /// `ct: tr` が成り立つかを宣言された impl から判定する
+    ///
+    /// `ct`, `tr` は `unif` で解決される
+    fn implements(
+        &mut self,
+        unif: &Unifier,
+        ct: &ConcreteType,
+        tr: &ConcreteTrait,
+        depth: usize,
+    ) -> Result<Implements> {
+        let ct = unif.resolve(ct);
+        let tr = unif.resolve_trait(tr);
+        if let ConcreteType::Param { id, .. } = ct {
+            return self.param_implements(unif, id, &tr);
+        }
+        // 探索中の目標に戻ってきた場合は打ち切る
+        let key = (ct.clone(), tr.clone());
+        if depth == 0 || self.goals.contains(&key) {
+            return Ok(Implements::Unknown);
+        }
+        self.goals.push(key);
+        let res = self.implements_by_impls(unif, &ct, &tr, depth);
+        self.goals.pop();
+        res
+    }
+    /// `ct` が境界 `b` を満たすか
+    fn bound_implements(
+        &mut self,
+        unif: &Unifier,
+        ct: &ConcreteType,
+        b: &ConcreteBound,
+        depth: usize,
+    ) -> Result<Implements> {
+        let mut res = Implements::Yes;
+        for t in &b.pos {
+            res = res.and(self.implements(unif, ct, t, depth)?);
+        }
+        for t in &b.neg {
+            res = res.and(self.implements(unif, ct, t, depth)?.not());
+        }
+        for f in &b.formulas {
+            res = res.and(self.formula_implements(unif, ct, f, depth)?);
         }
         Ok(res)
     }
+    fn implements_by_impls(
+        &mut self,
+        unif: &Unifier,
+        ct: &ConcreteType,
+        tr: &ConcreteTrait,
+        depth: usize,
+    ) -> Result<Implements> {
+        let goal = ConcreteImpl {
+            trait_exp: tr.clone(),
+            impl_for: ct.clone(),
+            equalities: Vec::new(),
+            disequalities: Vec::new(),
+            type_bounds: Vec::new(),
+            projections: Vec::new(),
+            assoc: Vec::new(),
+        };
+        let checker = self.checker;
+        let mut res = Implements::No;
+        for im in checker.impls.iter().filter(|i| i.trait_exp.id == tr.id) {
+            let base = self.params.len();
+            let cand = self.get_concrete_impl(im)?;
+            let end = self.params.len();
+            let mut u = unif.clone();
+            if !Self::unify_headers(&mut u, &goal, &cand)? {
+                continue;
+            }
+            let mut applies = Implements::Yes;
+            for id in base..end {
+                if let Some(b) = self.params[id].clone() {
+                    let p = ConcreteType::Param {
+                        id,
+                        name: String::new(),
+                    };
+                    applies = applies.and(self.bound_implements(&u, &p, &b, depth - 1)?);
+                }
+            }
+            for (ty, b) in &cand.type_bounds {
+                applies = applies.and(self.bound_implements(&u, ty, b, depth - 1)?);
+            }
+            // 射影の値は impl からは決められない
+            if applies == Implements::Yes && !cand.projections.is_empty() {
+                applies = Implements::Unknown;
+            }
+            // 検査対象側の型変数が具体化される場合は、その具体化の下でしか成り立たない
+            if applies == Implements::Yes && !Self::only_renames(&u, &goal, base) {
+                applies = Implements::Unknown;
+            }
+            match applies {
+                Implements::Yes => return Ok(Implements::Yes),
+                Implements::Unknown => res = Implements::Unknown,
+                Implements::No => {}
+            }
+        }
+        if res == Implements::No && !checker.options.closed_world {
+            res = Implements::Unknown;
+        }
+        if res == Implements::Unknown && self.excluded_by_impls(unif, ct, tr, depth)? {
+            res = Implements::No;
+        }
+        Ok(res)
+    }
+    /// `ct` が、`tr` の A' に含まれるトレイトを負の上位トレイトとして除外するトレイトを実装するか
+    fn excluded_by_impls(
+        &mut self,
+        unif: &Unifier,
+        ct: &ConcreteType,
+        tr: &ConcreteTrait,
+        depth: usize,
+    ) -> Result<bool> {
+        let ct = &unif.resolve(ct);
+        let a_tr = self.a_d(ct, tr)?;
+        let checker = self.checker;
+        for im in &checker.impls {
+            let Some(b) = &checker.traits[im.trait_exp.id.0].supertraits else {
+                continue;
+            };
+            if b.neg.is_empty() {
+                continue;
+            }
+            let cand = self.get_concrete_impl(im)?;
+            if cand.has_predicates() {
+                continue;
+            }
+            // 検査対象側の型変数は具体化しない
+            let mut u = unif.clone();
+            if !u.type_match(&cand.impl_for, ct).is_ok() {
+                continue;
+            }
+            let e = u.resolve_trait(&cand.trait_exp);
+            let excludes = self
+                .excluded_by(ct, &e)?
+                .iter()
+                .any(|n| a_tr.contains(&u.resolve_trait(n)));
+            if excludes && self.implements(&u, ct, &e, depth - 1)? == Implements::Yes {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    /// 型変数 `id` が `tr` を実装するかを、単一化された検査対象の型変数の境界から判定する
+    fn param_implements(
+        &self,
+        unif: &Unifier,
+        id: usize,
+        tr: &ConcreteTrait,
+    ) -> Result<Implements> {
+        let mut bound = ConcreteBound::default();
+        for p in unif.class_of(unif.root(id)) {
+            if let Some(Some(b)) = self.params.get(p).filter(|_| p < self.checked_params) {
+                bound = ConcreteBound::join(&bound, b);
+            }
+        }
+        let bound = unif.resolve_bound(&bound);
+        let ty = unif.resolve(&self.param_ty(id));
+        if self.a(&ty, &bound)?.contains(tr) {
+            return Ok(Implements::Yes);
+        }
+        // tr を除くと満たせなくなるなら実装する
+        if self.needs_sat(&ty, &bound)? {
+            let mut without_tr = bound.clone();
+            without_tr.neg.insert(tr.clone());
+            if self.unsatisfiable(&ty, &without_tr)? {
+                return Ok(Implements::Yes);
+            }
+        }
+        // tr を加えると満たせなくなるなら実装しない
+        let mut with_tr = bound;
+        with_tr.pos.insert(tr.clone());
+        if self.unsatisfiable(&ty, &with_tr)? {
+            return Ok(Implements::No);
+        }
+        Ok(Implements::Unknown)
+    }
+    /// `goal` に現れる `base` 未満の型変数が、互いに異なる型変数にしか束縛されていないか
+    fn only_renames(unif: &Unifier, goal: &ConcreteImpl, base: usize) -> bool {
+        fn collect(ct: &ConcreteType, ids: &mut HashSet<usize>) {
+            match ct {
+                ConcreteType::Type { params, .. } => params.iter().for_each(|p| collect(p, ids)),
+                ConcreteType::Param { id, .. } => {
+                    ids.insert(*id);
+                }
+            }
+        }
+        let mut ids = HashSet::new();
+        collect(&goal.impl_for, &mut ids);
+        goal.trait_exp
+            .params
+            .iter()
+            .for_each(|p| collect(p, &mut ids));
+        let mut images = HashSet::new();
+        for id in ids.into_iter().filter(|id| *id < base) {
+            let p = ConcreteType::Param {
+                id,
+                name: String::new(),
+            };
+            match unif.resolve(&p) {
+                ConcreteType::Param { id, .. } => {
+                    if !images.insert(id) {
+                        return false;
+                    }
+                }
+                ConcreteType::Type { .. } => return false,
+            }
+        }
+        true
+    }
+    /// 具体型 `ct` が境界 `b` を満たし得ないか
+    fn check_concrete_bound(
+        &mut self,
+        unif: &Unifier,
+        ct: &ConcreteType,
+        b: &ConcreteBound,
+    ) -> Result<ConflictCheckResult> {
+        if self.unsatisfiable(&unif.resolve(ct), &unif.resolve_bound(b))? {
+            return Ok(ConflictCheckResult::NonConflict);
+        }
+        if self.bound_implements(unif, ct, b, IMPL_SEARCH_DEPTH)? == Implements::No {
+            return Ok(ConflictCheckResult::NonConflict);
+        }
+        Ok(ConflictCheckResult::Conflict)
+    }
+
+    /// impl を具体化する
+    ///
+    /// 境界は後に宣言された型変数も参照できるので、全ての型変数を確保してから境界を具体化する
+    pub fn get_concrete_impl(&mut self, im: &Impl) -> Result<ConcreteImpl> {
+        let base = self.params.len();
+        for p in &im.params {
+            // 検査対象の impl 同士で同名の型変数を区別できるようにする
+            let mut name = p.name.clone();
+            while self.checked_params == 0 && self.param_names.contains(&name) {
+                name.push('\'');
+            }
+            self.param_names.push(name);
+            self.params.push(None);
+        }
+        let args: Vec<_> = (base..self.params.len())
+            .map(|id| ConcreteType::Param {
+                id,
+                name: self.param_names[id].clone(),
+            })
+            .collect();
+        // 射影は impl ごとに、内側のものから順に型変数を確保する
+        self.projections.clear();
+        let mut projections = Vec::new();
+        for b in im.params.iter().filter_map(|p| p.bound.as_ref()) {
+            b.projections(&mut projections);
+        }
+        im.trait_exp.projections(&mut projections);
+        im.impl_for.projections(&mut projections);
+        for (_, t) in &im.assoc {
+            t.projections(&mut projections);
+        }
+        for p in &im.predicates {
+            match p {
+                Predicate::Eq(l, r) | Predicate::Ne(l, r) => {
+                    l.projections(&mut projections);
+                    r.projections(&mut projections);
+                }
+                Predicate::Bound(t, b) => {
+                    t.projections(&mut projections);
+                    b.projections(&mut projections);
+                }
+            }
+        }
+        for ty in projections {
+            if let Ty::Projection {
+                self_ty,
+                trait_ref,
+                name,
+            } = ty
+            {
+                let self_ty = self.lower_type(&args, self_ty)?;
+                let trait_ref = self.lower_trait(&args, trait_ref)?;
+                self.projection(self_ty, trait_ref, name);
+            }
+        }
+        let mut equalities = Vec::new();
+        for (id, p) in (base..).zip(&im.params) {
+            if let Some(b) = &p.bound {
+                self.params[id] = Some(self.lower_bound(&args, b)?);
+                self.assoc_equalities(&args, &args[id - base], b, &mut equalities)?;
+            }
+        }
+        let trait_params = im
+            .trait_exp
+            .params
+            .iter()
+            .map(|p| self.lower_type(&args, p))
+            .collect::<Result<Vec<_>, _>>()?;
+        let impl_for = self.lower_type(&args, &im.impl_for)?;
+        // ヘッダに現れる構造体の型変数の境界を、その位置の型変数が満たすとみなす
+        let mut implied = Vec::new();
+        if self.implied_bounds {
+            self.implied_bounds(&impl_for, &mut implied)?;
+            for p in &trait_params {
+                self.implied_bounds(p, &mut implied)?;
+            }
+        }
+        for (id, b) in implied {
+            let joined = match &self.params[id] {
+                Some(own) => ConcreteBound::join(own, &b),
+                None => b,
+            };
+            self.params[id] = Some(joined);
+        }
+        let trait_exp = ConcreteTrait {
+            id: im.trait_exp.id,
+            name: im.trait_exp.name.clone(),
+            params: trait_params,
+        };
+        let mut disequalities = Vec::new();
+        let mut type_bounds = Vec::new();
+        for p in &im.predicates {
+            match p {
+                Predicate::Eq(l, r) => {
+                    equalities.push((self.lower_type(&args, l)?, self.lower_type(&args, r)?))
+                }
+                Predicate::Ne(l, r) => {
+                    disequalities.push((self.lower_type(&args, l)?, self.lower_type(&args, r)?))
+                }
+                // 型変数への境界は、型変数リストに書いた境界と同じに扱う
+                Predicate::Bound(ty, b) => {
+                    let ty = self.lower_type(&args, ty)?;
+                    self.assoc_equalities(&args, &ty, b, &mut equalities)?;
+                    match ty {
+                        ConcreteType::Param { id, .. } => {
+                            let b = self.lower_bound(&args, b)?;
+                            let joined = match &self.params[id] {
+                                Some(own) => ConcreteBound::join(own, &b),
+                                None => b,
+                            };
+                            self.params[id] = Some(joined);
+                        }
+                        ty => type_bounds.push((ty, self.lower_bound(&args, b)?)),
+                    }
+                }
+            }
+        }
+        let assoc = im
+            .assoc
+            .iter()
+            .map(|(name, t)| Ok((name.clone(), self.lower_type(&args, t)?)))
+            .collect::<Result<_>>()?;
+        let cimpl = ConcreteImpl {
+            trait_exp,
+            impl_for,
+            equalities,
+            disequalities,
+            type_bounds,
+            projections: std::mem::take(&mut self.projections),
+            assoc,
+        };
+        Ok(cimpl)
+    }
+
+    /// 型式中の構造体の、型変数の位置にある引数が満たす境界を集める
+    fn implied_bounds(
+        &self,
+        ct: &ConcreteType,
+        res: &mut Vec<(usize, ConcreteBound)>,
+    ) -> Result<()> {
+        if let ConcreteType::Type { id, params, .. } = ct {
+            let Struct {
+                params: decl_params,
+                predicates,
+                ..
+            } = &self.checker.structs[id.0];
+            for (ty, b) in self.declared_bounds(decl_params, predicates, params)? {
+                if let ConcreteType::Param { id, .. } = ty {
+                    res.push((id, b));
+                }
+            }
+            for p in params {
+                self.implied_bounds(p, res)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// where 節の等式を単一化し、不等式を単一化の失敗条件に加える
+    fn apply_predicates(unif: &mut Unifier, ci: &ConcreteImpl) -> UnificationResult {
+        for (l, r) in &ci.equalities {
+            let res = unif.type_unify(l, r);
+            if !res.is_ok() {
+                return res;
+            }
+        }
+        for (l, r) in &ci.disequalities {
+            let res = unif.type_disunify(l, r);
+            if !res.is_ok() {
+                return res;
+            }
+        }
+        UnificationResult::Ok
+    }
+    /// 単一化の下で同じ射影になるものの値を単一化する。関連型の指定が食い違えば失敗する
+    fn unify_projections<'p>(
+        unif: &mut Unifier,
+        projections: impl Iterator<Item = &'p Projection>,
+    ) -> UnificationResult {
+        let projections: Vec<_> = projections.collect();
+        // 値の単一化で別の射影どうしが同じになり得るので、変化が無くなるまで繰り返す
+        loop {
+            let mut changed = false;
+            for (i, p) in projections.iter().enumerate() {
+                for q in &projections[i + 1..] {
+                    if p.name != q.name
+                        || unif.resolve(&p.value) == unif.resolve(&q.value)
+                        || unif.resolve(&p.self_ty) != unif.resolve(&q.self_ty)
+                        || unif.resolve_trait(&p.trait_ref) != unif.resolve_trait(&q.trait_ref)
+                    {
+                        continue;
+                    }
+                    let res = unif.type_unify(&p.value, &q.value);
+                    if !res.is_ok() {
+                        return res;
+                    }
+                    changed = true;
+                }
+            }
+            if !changed {
+                return UnificationResult::Ok;
+            }
+        }
+    }
+    /// ヘッダの単一化。両方の where 節も適用する
+    ///
+    /// パラメータ数の不一致はプログラムの誤りなのでエラーとする
+    fn unify_headers(unif: &mut Unifier, c1: &ConcreteImpl, c2: &ConcreteImpl) -> Result<bool> {
+        let mut res = unif.trait_unify(&c1.trait_exp, &c2.trait_exp);
+        if res.is_ok() {
+            res = unif.type_unify(&c1.impl_for, &c2.impl_for);
+        }
+        if res.is_ok() {
+            res = Self::apply_predicates(unif, c1);
+        }
+        if res.is_ok() {
+            res = Self::apply_predicates(unif, c2);
+        }
+        if res.is_ok() {
+            res = Self::unify_projections(unif, c1.projections.iter().chain(&c2.projections));
+        }
+        match res {
+            UnificationResult::Ok => Ok(true),
+            UnificationResult::Failure(e @ UnificationError::Arity { .. }) => {
+                Err(anyhow!("Unification error: {}", e))
+            }
+            UnificationResult::Failure(_) => Ok(false),
+        }
+    }
+
+    /// 射影の型とトレイトが impl のヘッダに一致すれば、射影をその impl の関連型の定義と単一化する
+    ///
+    /// 境界も where 節も持たない impl だけを使う。定義と両立しなければ false
+    fn project_by_impls(&mut self, unif: &mut Unifier, projections: &[Projection]) -> Result<bool> {
+        let checker = self.checker;
+        for p in projections {
+            let self_ty = unif.resolve(&p.self_ty);
+            let trait_ref = unif.resolve_trait(&p.trait_ref);
+            for im in checker
+                .impls
+                .iter()
+                .filter(|im| im.trait_exp.id == trait_ref.id)
+            {
+                if im.params.iter().any(|p| p.bound.is_some()) || !im.predicates.is_empty() {
+                    continue;
+                }
+                let cand = self.get_concrete_impl(im)?;
+                let mut u = unif.clone();
+                if !u.type_match(&cand.impl_for, &self_ty).is_ok()
+                    || !u.trait_match(&cand.trait_exp, &trait_ref).is_ok()
+                {
+                    continue;
+                }
+                let Some((_, def)) = cand.assoc.iter().find(|(n, _)| *n == p.name) else {
+                    continue;
+                };
+                if !unif.type_unify(&p.value, &u.resolve(def)).is_ok() {
+                    return Ok(false);
+                }
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    /// `range` の型変数のうち、ヘッダに現れず、where 節の等式からも決まらないもの
+    fn unconstrained(ci: &ConcreteImpl, range: std::ops::Range<usize>) -> Vec<usize> {
+        let mut constrained: HashSet<_> = range
+            .clone()
+            .filter(|id| {
+                ci.impl_for.contains_param(*id)
+                    || ci.trait_exp.params.iter().any(|p| p.contains_param(*id))
+            })
+            .collect();
+        // 一方の辺の型変数が全て決まれば、他方の辺の型変数も決まる
+        loop {
+            let mut changed = false;
+            for (l, r) in &ci.equalities {
+                for (from, to) in [(l, r), (r, l)] {
+                    if range
+                        .clone()
+                        .any(|id| from.contains_param(id) && !constrained.contains(&id))
+                    {
+                        continue;
+                    }
+                    for id in range.clone().filter(|id| to.contains_param(*id)) {
+                        changed |= constrained.insert(id);
+                    }
+                }
+            }
+            // 射影の値は、射影の型とトレイトの型変数が全て決まれば決まる
+            for p in &ci.projections {
+                let ConcreteType::Param { id: value, .. } = p.value else {
+                    continue;
+                };
+                let determined = range.clone().all(|id| {
+                    constrained.contains(&id)
+                        || !(p.self_ty.contains_param(id)
+                            || p.trait_ref.params.iter().any(|t| t.contains_param(id)))
+                });
+                if determined {
+                    changed |= constrained.insert(value);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        range.filter(|id| !constrained.contains(id)).collect()
+    }
+    /// ヘッダに現れず、impl の適用から決まらない型変数を報告する
+    fn unconstrained_params(checker: &'a Checker, im: &Impl) -> Result<Vec<Diagnostic>> {
+        let mut env = Self::new(checker);
+        let ci = env.get_concrete_impl(im)?;
+        Ok(Self::unconstrained(&ci, 0..env.params.len())
+            .into_iter()
+            .filter(|id| *id < im.params.len())
+            .map(|id| Diagnostic::UnconstrainedParam {
+                impl_: im.to_string(),
+                param: im.params[id].name.clone(),
+            })
+            .collect())
+    }
+
+    /// impl 自身の境界が充足不能でないかを調べる
+    fn dead_impl(checker: &'a Checker, im: &Impl) -> Result<Vec<Diagnostic>> {
+        let mut env = Self::new(checker);
+        // 導かれる境界と impl の境界が矛盾するなら ill_formed が報告するので、ここでは仮定しない
+        env.implied_bounds = false;
+        let ci = env.get_concrete_impl(im)?;
+        env.implied_bounds = true;
+        env.checked_params = env.params.len();
+        if checker.options.blanket_depth.is_some() {
+            env.lower_blankets()?;
+        }
+        let mut res = Vec::new();
+        let mut unif = Unifier::new();
+        if let UnificationResult::Failure(e) = Self::apply_predicates(&mut unif, &ci) {
+            res.push(Diagnostic::UnsatisfiablePredicates {
+                impl_: im.to_string(),
+                reason: e.to_string(),
+            });
+            unif = Unifier::new();
+        }
+        // where 節で等しいとされた型変数の境界は、代表の型変数でまとめて調べる
+        // 射影を表す型変数も含める
+        let mut bounds = Vec::new();
+        for id in 0..env.checked_params {
+            if unif.root(id) != id {
+                continue;
+            }
+            let mut class: Vec<_> = unif.class_of(id).into_iter().collect();
+            class.sort();
+            let mut bound = None;
+            for b in class.iter().filter_map(|c| env.params[*c].as_ref()) {
+                bound = Some(match bound {
+                    Some(acc) => ConcreteBound::join(&acc, b),
+                    None => b.clone(),
+                });
+            }
+            if let Some(b) = bound {
+                let ty = unif.resolve(&env.param_ty(id));
+                bounds.push((env.param_names[id].clone(), ty, unif.resolve_bound(&b)));
+            }
+        }
+        for (ty, b) in &ci.type_bounds {
+            if env.unsatisfiable(ty, b)? {
+                res.push(Diagnostic::UnsatisfiableBound {
+                    impl_: im.to_string(),
+                    param: ty.to_string(),
+                    bound: b.to_string(),
+                });
+            }
+        }
+        for (p, ty, b) in &bounds {
+            let a = env.a(ty, b)?;
+            let mut dead = Vec::new();
+            for n in b.neg.iter().filter(|n| a.contains(n)) {
+                dead.push((n, None));
+            }
+            // 負の上位トレイトにより除外されるトレイトは、除外するトレイトも示す
+            let mut excluders: Vec<_> = a.iter().collect();
+            excluders.sort_by_key(|t| t.to_string());
+            for u in excluders {
+                for n in env.excluded_by(ty, u)? {
+                    if let Some(n) = a.get(&n) {
+                        dead.push((n, Some(u.to_string())));
+                    }
+                }
+            }
+            let reported = !dead.is_empty();
+            for (n, by) in dead {
+                // 単独で n を導く正の境界があればそれを示す
+                let mut pos = None;
+                for t in &b.pos {
+                    let single = ConcreteBound {
+                        pos: HashSet::from([t.clone()]),
+                        neg: b.neg.clone(),
+                        ..Default::default()
+                    };
+                    if env.a(ty, &single)?.contains(n) {
+                        pos = Some(t.to_string());
+                        break;
+                    }
+                }
+                let pos = pos.unwrap_or_else(|| {
+                    ConcreteBound {
+                        pos: b.pos.clone(),
+                        ..Default::default()
+                    }
+                    .to_string()
+                });
+                res.push(Diagnostic::DeadImpl {
+                    impl_: im.to_string(),
+                    param: p.clone(),
+                    pos,
+                    neg: n.to_string(),
+                    by,
+                });
+            }
+            if !reported && env.unsatisfiable(ty, b)? {
+                res.push(Diagnostic::UnsatisfiableBound {
+                    impl_: im.to_string(),
+                    param: p.clone(),
+                    bound: b.to_string(),
+                });
+            }
+        }
+        Ok(res)
+    }
+
+    /// 構造体・トレイトの型変数と where 節に宣言された境界を、`args` で具体化する
+    fn declared_bounds(
+        &self,
+        decl_params: &[Param],
+        predicates: &[Predicate],
+        args: &[ConcreteType],
+    ) -> Result<Vec<(ConcreteType, ConcreteBound)>> {
+        let mut res = Vec::new();
+        for (p, ty) in decl_params.iter().zip(args) {
+            if let Some(b) = &p.bound {
+                res.push((ty.clone(), self.lower_bound(args, b)?));
+            }
+        }
+        for p in predicates {
+            if let Predicate::Bound(ty, b) = p {
+                res.push((self.lower_type(args, ty)?, self.lower_bound(args, b)?));
+            }
+        }
+        Ok(res)
+    }
+    /// `name<args>` の使用が、宣言された境界から課す条件を集める
+    fn declared_obligations(
+        &self,
+        decl_params: &[Param],
+        predicates: &[Predicate],
+        by: String,
+        args: &[ConcreteType],
+        res: &mut Vec<Obligation>,
+    ) -> Result<()> {
+        for (ty, b) in self.declared_bounds(decl_params, predicates, args)? {
+            let pos = b.pos.iter().map(|t| Formula::Atom(t.clone()));
+            let neg = b
+                .neg
+                .iter()
+                .map(|t| Formula::Not(Box::new(Formula::Atom(t.clone()))));
+            for bound in pos.chain(neg).chain(b.formulas.iter().cloned()) {
+                res.push(Obligation {
+                    ty: ty.clone(),
+                    bound,
+                    by: by.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+    fn type_obligations(&self, ct: &ConcreteType, res: &mut Vec<Obligation>) -> Result<()> {
+        if let ConcreteType::Type { id, params, .. } = ct {
+            let Struct {
+                params: decl_params,
+                predicates,
+                ..
+            } = &self.checker.structs[id.0];
+            self.declared_obligations(decl_params, predicates, ct.to_string(), params, res)?;
+            for p in params {
+                self.type_obligations(p, res)?;
+            }
+        }
+        Ok(())
+    }
+    /// `ty: ct` の使用が課す条件を集める
+    fn trait_obligations(
+        &self,
+        ty: &ConcreteType,
+        ct: &ConcreteTrait,
+        res: &mut Vec<Obligation>,
+    ) -> Result<()> {
+        let tr = &self.checker.traits[ct.id.0];
+        self.declared_obligations(
+            &tr.params,
+            &tr.predicates,
+            ct.to_string(),
+            &Self::trait_args(ty, ct),
+            res,
+        )?;
+        for p in &ct.params {
+            self.type_obligations(p, res)?;
+        }
+        Ok(())
+    }
+
+    /// impl のヘッダと境界に現れる構造体・トレイトの使用が、宣言された境界を満たすかを調べる
+    ///
+    /// 満たさないと分かれば誤りとし、判定できなければ証明すべき条件として報告する
+    fn ill_formed(checker: &'a Checker, im: &Impl) -> Result<Vec<Diagnostic>> {
+        let mut env = Self::new(checker);
+        // 導かれる境界は使用が整形式であることを前提とするので、ここでは宣言した境界だけを仮定する
+        env.implied_bounds = false;
+        let ci = env.get_concrete_impl(im)?;
+        env.implied_bounds = true;
+        env.checked_params = env.params.len();
+        if checker.options.blanket_depth.is_some() {
+            env.lower_blankets()?;
+        }
+        let mut obligations = Vec::new();
+        env.trait_obligations(&ci.impl_for, &ci.trait_exp, &mut obligations)?;
+        // 実装先の型は、トレイトの負の上位トレイトを実装してはならない
+        for n in env.excluded_by(&ci.impl_for, &ci.trait_exp)? {
+            obligations.push(Obligation {
+                ty: ci.impl_for.clone(),
+                bound: Formula::Not(Box::new(Formula::Atom(n))),
+                by: ci.trait_exp.to_string(),
+            });
+        }
+        env.type_obligations(&ci.impl_for, &mut obligations)?;
+        let bounds = env.params[..env.checked_params]
+            .iter()
+            .enumerate()
+            .filter_map(|(id, b)| Some((env.param_ty(id), b.as_ref()?)));
+        for (ty, b) in bounds.chain(ci.type_bounds.iter().map(|(ty, b)| (ty.clone(), b))) {
+            let mut atoms: Vec<_> = b.pos.iter().chain(b.neg.iter()).collect();
+            for f in &b.formulas {
+                f.atoms(&mut atoms);
+            }
+            for t in atoms {
+                env.trait_obligations(&ty, t, &mut obligations)?;
+            }
+        }
+        for (ty, _) in &ci.type_bounds {
+            env.type_obligations(ty, &mut obligations)?;
+        }
+        let unif = Unifier::new();
+        let mut res = Vec::new();
+        for o in obligations {
+            let holds = env.formula_implements(&unif, &o.ty, &o.bound, IMPL_SEARCH_DEPTH)?;
+            let bound = match &o.bound {
+                Formula::Not(g) if matches!(**g, Formula::Atom(_)) => format!("!{}", g),
+                f => f.to_string(),
+            };
+            let d = match holds {
+                Implements::Yes => continue,
+                Implements::No => Diagnostic::IllFormed {
+                    impl_: im.to_string(),
+                    ty: o.ty.to_string(),
+                    bound,
+                    by: o.by,
+                },
+                Implements::Unknown => Diagnostic::Obligation {
+                    impl_: im.to_string(),
+                    ty: o.ty.to_string(),
+                    bound,
+                    by: o.by,
+                },
+            };
+            if !res.contains(&d) {
+                res.push(d);
+            }
+        }
+        Ok(res)
+    }
+
+    /// `general` が `specific` の適用される全ての型を覆うか
+    ///
+    /// ヘッダを一方向に単一化し、`specific` の境界から `general` の境界が導けるかを調べる
+    fn subsumes(checker: &'a Checker, general: &Impl, specific: &Impl) -> Result<bool> {
+        let mut env = Self::new(checker);
+        let cs = env.get_concrete_impl(specific)?;
+        env.checked_params = env.params.len();
+        if checker.options.blanket_depth.is_some() {
+            env.lower_blankets()?;
+        }
+        let base = env.params.len();
+        let cg = env.get_concrete_impl(general)?;
+        let end = env.params.len();
+        let mut unif = Unifier::new();
+        // specific の where 節の下で考える。満たせない場合は dead_impl で報告される
+        if !Self::apply_predicates(&mut unif, &cs).is_ok() {
+            return Ok(false);
+        }
+        let cs_trait = unif.resolve_trait(&cs.trait_exp);
+        let cs_for = unif.resolve(&cs.impl_for);
+        if !unif.trait_match(&cg.trait_exp, &cs_trait).is_ok()
+            || !unif.type_match(&cg.impl_for, &cs_for).is_ok()
+        {
+            return Ok(false);
+        }
+        for (l, r) in &cg.equalities {
+            if unif.resolve(l) != unif.resolve(r) {
+                return Ok(false);
+            }
+        }
+        for (l, r) in &cg.disequalities {
+            if unif.clone().type_unify(l, r).is_ok() {
+                return Ok(false);
+            }
+        }
+        for id in base..end {
+            let Some(b) = env.params[id].clone() else {
+                continue;
+            };
+            let p = unif.resolve(&ConcreteType::Param {
+                id,
+                name: env.param_names[id].clone(),
+            });
+            if env.bound_implements(&unif, &p, &b, IMPL_SEARCH_DEPTH)? != Implements::Yes {
+                return Ok(false);
+            }
+        }
+        for (ty, b) in &cg.type_bounds {
+            if env.bound_implements(&unif, ty, b, IMPL_SEARCH_DEPTH)? != Implements::Yes {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn check_impls(
+        checker: &'a Checker,
+        i1: &Impl,
+        i2: &Impl,
+    ) -> Result<(ConflictCheckResult, Vec<Overlap>)> {
+        let mut env = Self::new(checker);
+        let c1 = env.get_concrete_impl(i1)?;
+        let mid = env.params.len();
+        let c2 = env.get_concrete_impl(i2)?;
+        env.checked_params = env.params.len();
+        if checker.options.blanket_depth.is_some() {
+            env.lower_blankets()?;
+        }
+        let mut unif = Unifier::new();
+        if !Self::unify_headers(&mut unif, &c1, &c2)? {
+            return Ok((ConflictCheckResult::NonConflict, Vec::new()));
+        }
+        let projections: Vec<_> = c1
+            .projections
+            .iter()
+            .chain(&c2.projections)
+            .cloned()
+            .collect();
+        if !env.project_by_impls(&mut unif, &projections)? {
+            return Ok((ConflictCheckResult::NonConflict, Vec::new()));
+        }
+        let mut existentials = Vec::new();
+        if checker.options.existential {
+            existentials.extend(
+                Self::unconstrained(&c1, 0..mid)
+                    .into_iter()
+                    .chain(Self::unconstrained(&c2, mid..env.checked_params))
+                    .filter(|id| env.params[*id].is_some()),
+            );
+            existentials.sort();
+        }
+        // 閉世界では、存在する型変数に入り得る型を全て試し、どれでも衝突しなければ衝突しない
+        let Some(instances) = env.existential_instances(&existentials)? else {
+            return env.check_routes(&unif, &c1, &c2, &existentials);
+        };
+        for instance in instances {
+            let mut unif = unif.clone();
+            let unified = existentials
+                .iter()
+                .zip(&instance)
+                .all(|(id, ty)| unif.type_unify(&env.param_ty(*id), ty).is_ok());
+            if !unified {
+                continue;
+            }
+            let res = env.check_routes(&unif, &c1, &c2, &existentials)?;
+            if res.0 == ConflictCheckResult::Conflict {
+                return Ok(res);
+            }
+        }
+        Ok((ConflictCheckResult::NonConflict, Vec::new()))
+    }
+
+    /// 存在する型変数に入り得る型の組を全て求める
+    ///
+    /// 閉世界で、境界の正のトレイトのどれかの impl が全て型変数を持たない場合だけ求める
+    fn existential_instances(&self, ids: &[usize]) -> Result<Option<Vec<Vec<ConcreteType>>>> {
+        if !self.checker.options.closed_world || ids.is_empty() {
+            return Ok(None);
+        }
+        let mut instances = vec![Vec::new()];
+        for id in ids {
+            let Some(b) = &self.params[*id] else {
+                return Ok(None);
+            };
+            let mut traits: Vec<_> = b.pos.iter().collect();
+            traits.sort_by_key(|t| t.to_string());
+            let mut found = None;
+            for t in traits {
+                found = self.instances_of(t)?;
+                if found.is_some() {
+                    break;
+                }
+            }
+            let Some(candidates) = found else {
+                return Ok(None);
+            };
+            if instances.len() * candidates.len() > EXISTENTIAL_INSTANCES {
+                return Ok(None);
+            }
+            instances = instances
+                .into_iter()
+                .flat_map(|i| {
+                    candidates.iter().map(move |c| {
+                        let mut i = i.clone();
+                        i.push(c.clone());
+                        i
+                    })
+                })
+                .collect();
+        }
+        Ok(Some(instances))
+    }
+
+    /// トレイト `t` の impl の実装先の型。型変数を持つ impl があれば求めない
+    fn instances_of(&self, t: &ConcreteTrait) -> Result<Option<Vec<ConcreteType>>> {
+        let mut res = Vec::new();
+        for im in self
+            .checker
+            .impls
+            .iter()
+            .filter(|im| im.trait_exp.id == t.id)
+        {
+            if !im.params.is_empty() {
+                return Ok(None);
+            }
+            let ty = self.lower_type(&[], &im.impl_for)?;
+            if !res.contains(&ty) {
+                res.push(ty);
+            }
+        }
+        Ok(Some(res))
+    }
+
+    /// 単一化した二つの impl の経路を調べる。どれか一つの経路で境界が両立しなければ衝突しない
+    fn check_routes(
+        &mut self,
+        unif: &Unifier,
+        c1: &ConcreteImpl,
+        c2: &ConcreteImpl,
+        existentials: &[usize],
+    ) -> Result<(ConflictCheckResult, Vec<Overlap>)> {
+        // 同じ型式に束縛された経路は、その型式が境界をまとめて満たせるかを調べる
+        let mut params: Vec<(Option<ConcreteType>, HashSet<usize>)> = Vec::new();
+        for (ct, ps) in unif.get_all_unified_params() {
+            match params.iter_mut().find(|(c, _)| ct.is_some() && *c == ct) {
+                Some((_, acc)) => acc.extend(ps),
+                None => params.push((ct, ps)),
+            }
+        }
+        // ヘッダに現れない型変数は単一化されないので、それぞれ一つの経路とする
+        let unified: HashSet<_> = params
+            .iter()
+            .flat_map(|(_, ps)| ps.iter().cloned())
+            .collect();
+        params.extend(
+            existentials
+                .iter()
+                .filter(|id| !unified.contains(id))
+                .map(|id| (None, HashSet::from([*id]))),
+        );
+        let mut overlaps = Vec::new();
+        for (ct, ps) in params {
+            let mut bound = ConcreteBound::default();
+            let mut ps: Vec<_> = ps.into_iter().collect();
+            ps.sort();
+            for p in ps.iter() {
+                if let Some(b) = &self.params[*p] {
+                    bound = ConcreteBound::join(&bound, b);
+                }
+            }
+            let res = if let Some(ct) = &ct {
+                // 型変数と型式が単一化された場合は、型式が境界を満たせるかを impl から調べる
+                // 将来課題：orphan rule などを考慮しながら、どうするか考える
+                self.check_concrete_bound(unif, ct, &bound)
+                    .with_context(|| format!("Bound {} check error for {}", bound, ct))?
+            } else {
+                self.check_bound(unif, &self.param_ty(ps[0]), &bound)
+                    .with_context(|| format!("Bound {} check error", bound))?
+            };
+            if res == ConflictCheckResult::NonConflict {
+                return Ok((ConflictCheckResult::NonConflict, Vec::new()));
+            }
+            let bound = unif.resolve_bound(&bound);
+            let self_ty = unif.resolve(&self.param_ty(ps[0]));
+            let witness = match (&ct, self.needs_sat(&self_ty, &bound)?) {
+                (None, true) => match self.satisfy(&self_ty, &bound)? {
+                    sat::Satisfy::Yes(model) => Some(self.witness(&self_ty, &bound, &model)?),
+                    _ => None,
+                },
+                _ => None,
+            };
+            // 型変数の名前は単一化の代表のものを使い、他の経路の型式と揃える
+            overlaps.push(Overlap {
+                param: self.param_names[unif.root(ps[0])].clone(),
+                existential: existentials.contains(&ps[0]),
+                ty: ct,
+                bound: self.normalize(&self_ty, &bound)?,
+                witness,
+            });
+        }
+        // 型変数でない型への境界は、単一化の結果を適用して同じ型になるものをまとめ、
+        // その型が満たせるかを調べる
+        let mut type_bounds: Vec<(ConcreteType, ConcreteBound)> = Vec::new();
+        for (ty, b) in c1.type_bounds.iter().chain(&c2.type_bounds) {
+            let ty = unif.resolve(ty);
+            let b = unif.resolve_bound(b);
+            match type_bounds.iter_mut().find(|(t, _)| *t == ty) {
+                Some((_, acc)) => *acc = ConcreteBound::join(acc, &b),
+                None => type_bounds.push((ty, b)),
+            }
+        }
+        for (ty, b) in type_bounds {
+            let res = self
+                .check_concrete_bound(unif, &ty, &b)
+                .with_context(|| format!("Bound {} check error for {}", b, ty))?;
+            if res == ConflictCheckResult::NonConflict {
+                return Ok((ConflictCheckResult::NonConflict, Vec::new()));
+            }
+            overlaps.push(Overlap {
+                param: ty.to_string(),
+                existential: false,
+                ty: None,
+                bound: self.normalize(&ty, &b)?,
+                witness: None,
+            });
+        }
+        Ok((ConflictCheckResult::Conflict, overlaps))
+    }
+}
+
+/// impl の組の検査結果。衝突する組には重なりの経路が付く
+pub type ImplPairResult = (ConflictCheckResult, Impl, Impl, Vec<Overlap>);
+
+/// 検査を行うやつ
+pub struct Checker {
+    /// `StructId`, `TraitId` を添字とする宣言
+    structs: Vec<Struct>,
+    traits: Vec<Trait>,
+    impls: Vec<Impl>,
+    diagnostics: Vec<Diagnostic>,
+    options: CheckOptions,
+}
+
+impl Checker {
+    pub fn with_options(options: CheckOptions) -> Self {
+        Self {
+            structs: Vec::new(),
+            traits: Vec::new(),
+            impls: Vec::new(),
+            diagnostics: Vec::new(),
+            options,
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn insert(&mut self, p: ir::Program) -> Result<()> {
+        if !self.structs.is_empty() || !self.traits.is_empty() {
+            return Err(anyhow!("Program already inserted"));
+        }
+        self.structs = p.structs;
+        self.traits = p.traits;
+        self.impls = p.impls;
+        Ok(())
+    }
+
+    fn check_impls(&self, i1: &Impl, i2: &Impl) -> Result<(ConflictCheckResult, Vec<Overlap>)> {
+        ConflictCheckEnv::check_impls(self, i1, i2)
+    }
+
+    /// 上位トレイトの循環をトレイト名の水準で探す
+    fn supertrait_cycles(&self) -> Vec<Vec<String>> {
+        fn visit(
+            checker: &Checker,
+            id: TraitId,
+            path: &mut Vec<String>,
+            cycles: &mut Vec<Vec<String>>,
+        ) {
+            let Trait {
+                name, supertraits, ..
+            } = &checker.traits[id.0];
+            if let Some(pos) = path.iter().position(|n| n == name) {
+                // 最小の名前から始まるように回転して重複を除く
+                let mut cycle = path[pos..].to_vec();
+                let min = (0..cycle.len()).min_by_key(|i| &cycle[*i]).unwrap_or(0);
+                cycle.rotate_left(min);
+                if !cycles.contains(&cycle) {
+                    cycles.push(cycle);
+                }
+                return;
+            }
+            if let Some(b) = supertraits {
+                path.push(name.to_string());
+                for t in &b.pos {
+                    visit(checker, t.id, path, cycles);
+                }
+                path.pop();
+            }
+        }
+        let mut ids: Vec<_> = (0..self.traits.len()).map(TraitId).collect();
+        ids.sort_by_key(|id| &self.traits[id.0].name);
+        let mut cycles = Vec::new();
+        for id in ids {
+            visit(self, id, &mut Vec::new(), &mut cycles);
+        }
+        cycles
+    }
+
+    pub fn check(&mut self, p: ir::Program) -> Result<Vec<ImplPairResult>> {
+        let mut res = Vec::new();
+        self.insert(p)?;
+        for cycle in self.supertrait_cycles() {
+            self.diagnostics.push(Diagnostic::SupertraitCycle(cycle));
+        }
+        for im in &self.impls {
+            let unconstrained = ConflictCheckEnv::unconstrained_params(self, im)
+                .with_context(|| format!("implementation {} check error", im))?;
+            self.diagnostics.extend(unconstrained);
+        }
+        for im in &self.impls {
+            let dead = ConflictCheckEnv::dead_impl(self, im)
+                .with_context(|| format!("implementation {} check error", im))?;
+            self.diagnostics.extend(dead);
+        }
+        for im in &self.impls {
+            let obligations = ConflictCheckEnv::ill_formed(self, im)
+                .with_context(|| format!("implementation {} check error", im))?;
+            self.diagnostics.extend(obligations);
+        }
+        for (i, specific) in self.impls.iter().enumerate() {
+            for (j, general) in self.impls.iter().enumerate() {
+                // 同等な impl の組は後のものだけを報告する
+                if i == j || general.trait_exp.id != specific.trait_exp.id {
+                    continue;
+                }
+                let shadowed =
+                    ConflictCheckEnv::subsumes(self, general, specific).with_context(|| {
+                        format!("implementation {}, {} check error", general, specific)
+                    })?;
+                if shadowed && (j < i || !ConflictCheckEnv::subsumes(self, specific, general)?) {
+                    self.diagnostics.push(Diagnostic::ShadowedImpl {
+                        impl_: specific.to_string(),
+                        by: general.to_string(),
+                    });
+                }
+            }
+        }
+        for i in 0..self.impls.len() {
+            for j in (i + 1)..self.impls.len() {
+                let i1 = self.impls[i].clone();
+                let i2 = self.impls[j].clone();
+                let (result, overlaps) = self
+                    .check_impls(&i1, &i2)
+                    .with_context(|| format!("implementation {}, {} check error", i1, i2))?;
+                res.push((result, i1, i2, overlaps));
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod unifier_tests {
+    use super::*;
+
+    /// テストで使う構造体・トレイトの名前。位置を ID とする
+    const NAMES: [&str; 4] = ["Vec", "u8", "u16", "From"];
+    fn id_of(name: &str) -> usize {
+        NAMES.iter().position(|n| *n == name).unwrap()
+    }
+    fn ty(name: &str, params: Vec<ConcreteType>) -> ConcreteType {
+        ConcreteType::Type {
+            id: StructId(id_of(name)),
+            name: name.to_string(),
+            params,
+        }
+    }
+    fn param(id: usize) -> ConcreteType {
+        ConcreteType::Param {
+            id,
+            name: format!("T{}", id),
+        }
+    }
+    fn tr(name: &str, params: Vec<ConcreteType>) -> ConcreteTrait {
+        ConcreteTrait {
+            id: TraitId(id_of(name)),
+            name: name.to_string(),
+            params,
+        }
+    }
+
+    #[test]
+    fn param_unifies_with_itself() {
+        let mut unif = Unifier::new();
+        assert_eq!(unif.type_unify(&param(0), &param(0)), UnificationResult::Ok);
+        assert_eq!(unif.resolve(&param(0)), param(0));
+    }
+
+    #[test]
+    fn param_cycle_through_chain() {
+        let mut unif = Unifier::new();
+        assert!(unif.type_unify(&param(0), &param(1)).is_ok());
+        assert!(unif.type_unify(&param(1), &param(0)).is_ok());
+        assert_eq!(unif.resolve(&param(0)), unif.resolve(&param(1)));
+    }
+
+    #[test]
+    fn occurs_check() {
+        let mut unif = Unifier::new();
+        let res = unif.type_unify(&param(0), &ty("Vec", vec![param(0)]));
+        assert!(matches!(
+            res,
+            UnificationResult::Failure(UnificationError::Occurs { .. })
+        ));
+    }
+
+    #[test]
+    fn occurs_check_through_binding() {
+        let mut unif = Unifier::new();
+        assert!(unif
+            .type_unify(&param(0), &ty("Vec", vec![param(1)]))
+            .is_ok());
+        let res = unif.type_unify(&param(1), &param(0));
+        assert!(matches!(
+            res,
+            UnificationResult::Failure(UnificationError::Occurs { .. })
+        ));
+    }
+
+    #[test]
+    fn type_arity_mismatch() {
+        let mut unif = Unifier::new();
+        let res = unif.type_unify(
+            &ty("Vec", vec![param(0)]),
+            &ty("Vec", vec![param(1), param(2)]),
+        );
+        assert_eq!(
+            res,
+            UnificationResult::Failure(UnificationError::Arity {
+                name: "Vec".to_string(),
+                left: 1,
+                right: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn trait_arity_mismatch() {
+        let mut unif = Unifier::new();
+        let res = unif.trait_unify(&tr("From", vec![param(0)]), &tr("From", vec![]));
+        assert!(matches!(
+            res,
+            UnificationResult::Failure(UnificationError::Arity { .. })
+        ));
+    }
+
+    #[test]
+    fn name_mismatch() {
+        let mut unif = Unifier::new();
+        let res = unif.type_unify(&ty("u8", vec![]), &ty("u16", vec![]));
+        assert_eq!(
+            res,
+            UnificationResult::Failure(UnificationError::Mismatch(
+                "u8".to_string(),
+                "u16".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn nested_unification() {
+        let mut unif = Unifier::new();
+        let res = unif.trait_unify(
+            &tr("From", vec![ty("Vec", vec![param(0)])]),
+            &tr("From", vec![ty("Vec", vec![ty("u8", vec![])])]),
+        );
+        assert!(res.is_ok());
+        assert_eq!(unif.resolve(&param(0)), ty("u8", vec![]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `src` を名前解決・整形式性の検査を通してから検査する
+    pub(super) fn check_src(src: &str, options: CheckOptions) -> (Checker, Vec<ImplPairResult>) {
+        let program = parser::program(src).expect("Parse error").1;
+        let (lowered, messages) = lower(&program);
+        let lowered = lowered.unwrap_or_else(|| panic!("Errors: {:?}", messages));
+        let mut checker = Checker::with_options(options);
+        let results = checker.check(lowered).unwrap();
+        (checker, results)
+    }
+    /// `i` 番目と `j` 番目の impl の組の検査結果
+    pub(super) fn pair_result(
+        src: &str,
+        options: CheckOptions,
+        i: usize,
+        j: usize,
+    ) -> ImplPairResult {
+        let (checker, mut results) = check_src(src, options);
+        let n = checker.impls.len();
+        let k = (0..i).map(|a| n - 1 - a).sum::<usize>() + (j - i - 1);
+        results.swap_remove(k)
+    }
+    pub(super) fn pair(
+        src: &str,
+        options: CheckOptions,
+        i: usize,
+        j: usize,
+    ) -> ConflictCheckResult {
+        pair_result(src, options, i, j).0
+    }
+    /// 衝突する組の重なりの経路
+    pub(super) fn overlaps(src: &str, options: CheckOptions, i: usize, j: usize) -> Vec<String> {
+        let res = pair_result(src, options, i, j);
+        res.3.iter().map(|o| o.to_string()).collect()
+    }
+    pub(super) fn diagnostics(src: &str, options: CheckOptions) -> Vec<String> {
+        let (checker, _) = check_src(src, options);
+        checker
+            .diagnostics()
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+    fn closed_world() -> CheckOptions {
+        CheckOptions {
+            closed_world: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn deep_supertrait_closure() {
+        let src = "trait A: B {}
+trait B: C {}
+trait C: D {}
+trait D: E {}
+trait E {}
+trait Z {}
+trait Tr {}
+impl<T: A> Tr for T {}
+impl<T: Z - E> Tr for T {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn supertrait_params_substituted_at_depth() {
+        let src = "struct Vec<T>;
+struct u8;
+trait A<T>: B<Vec<T>> {}
+trait B<T> {}
+trait Z {}
+trait Tr {}
+impl<T: A<u8>> Tr for T {}
+impl<T: Z - B<Vec<u8>>> Tr for T {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn supertrait_cycle_reported() {
+        let src = "trait A: B {}
+trait B: C {}
+trait C: A {}
+trait Tr {}
+impl<T: A> Tr for T {}
+impl<T: C> Tr for T {}
+";
+        let (checker, results) = check_src(src, Default::default());
+        assert_eq!(
+            checker.diagnostics()[0].to_string(),
+            "error: supertrait cycle A -> B -> C -> A"
+        );
+        assert_eq!(results[0].0, ConflictCheckResult::Conflict);
+    }
+
+    #[test]
+    fn concrete_type_without_impl_closed_world() {
+        let src = "struct A;
+trait TA {}
+trait From<T> {}
+impl From<A> for A {}
+impl<T: TA> From<T> for A {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::Conflict
+        );
+        assert_eq!(
+            pair(src, closed_world(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn concrete_type_with_impl() {
+        let src = "struct A;
+trait TA {}
+trait From<T> {}
+impl From<A> for A {}
+impl<T: TA> From<T> for A {}
+impl TA for A {}
+";
+        assert_eq!(
+            pair(src, closed_world(), 0, 1),
+            ConflictCheckResult::Conflict
+        );
+    }
+
+    #[test]
+    fn concrete_type_negative_bound() {
+        let src = "struct A;
+trait TA {}
+trait From<T> {}
+impl From<A> for A {}
+impl<T: - TA> From<T> for A {}
+impl TA for A {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn recurring_goal_stops() {
+        let src = "struct A;
+trait TA {}
+trait TB {}
+trait From<T> {}
+impl From<A> for A {}
+impl<T: TA> From<T> for A {}
+impl<T: TB> TA for T {}
+impl<T: TA> TB for T {}
+";
+        assert_eq!(
+            pair(src, closed_world(), 0, 1),
+            ConflictCheckResult::Conflict
+        );
+    }
+
+    #[test]
+    fn negative_bound_matched_after_unification() {
+        let src = "struct A;
+struct u8;
+struct str;
+struct Pair<T, U>;
+trait From<T> {}
+trait IntoIterator<T> {}
+impl<S, I: IntoIterator<S>> From<Pair<I, S>> for A {}
+impl<I: IntoIterator<u8> - IntoIterator<str>> From<Pair<I, str>> for A {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn generic_negative_bound_against_concrete_positive() {
+        let src = "struct A;
+struct u8;
+struct str;
+struct Pair<T, U>;
+trait From<T> {}
+trait IntoIterator<T> {}
+impl<I: IntoIterator<str>> From<Pair<I, str>> for A {}
+impl<S, I: IntoIterator<u8> - IntoIterator<S>> From<Pair<I, S>> for A {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn negative_bound_refers_to_unified_param() {
+        let src = "trait X {}
+trait IntoIterator<T> {}
+trait Tr<T> {}
+impl<S: X, I: IntoIterator<S>> Tr<S> for I {}
+impl<T: X, J: X - IntoIterator<T>> Tr<T> for J {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn bounds_flow_into_compound_type() {
+        let src = "struct Vec<T>;
+trait Clone {}
+trait Copy {}
+trait Tr {}
+impl<X: Copy> Clone for Vec<X> {}
+impl<T: Clone> Tr for T {}
+impl<U: - Copy> Tr for Vec<U> {}
+";
+        assert_eq!(
+            pair(src, closed_world(), 1, 2),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn inner_params_of_generic_struct() {
+        let src = "struct Vec<T>;
+struct Pair<T, U>;
+trait A {}
+trait Tr {}
+impl<T, U: A> Tr for Pair<T, U> {}
+impl<V: - A> Tr for Pair<Vec<V>, V> {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn later_route_proves_disjointness() {
+        let src = "struct u8;
+struct u16;
+trait From<T> {}
+trait IntoIterator<T> {}
+impl<I: IntoIterator<u16>, T: From<u8>> From<I> for T {}
+impl<I: IntoIterator<u16>, T: From<u16> - From<u8>> From<I> for T {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn unbounded_route_does_not_decide() {
+        let src = "trait A {}
+trait Tr<T> {}
+impl<U, T: A> Tr<U> for T {}
+impl<U, T: - A> Tr<U> for T {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn concrete_route_does_not_decide() {
+        let src = "struct u8;
+trait A {}
+trait Tr<T> {}
+impl<T: A> Tr<u8> for T {}
+impl<U, T: - A> Tr<U> for T {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn blanket_depth_changes_result() {
+        let src = "struct String;
+trait From<T> {}
+trait Display {}
+trait ToString {}
+trait Describe {}
+impl<T: Display> ToString for T {}
+impl<T: ToString> Describe for T {}
+impl<T: Display> From<T> for String {}
+impl<T: Display - Describe> From<T> for String {}
+";
+        let depth = |d| CheckOptions {
+            blanket_depth: Some(d),
+            ..Default::default()
+        };
+        assert_eq!(
+            pair(src, Default::default(), 2, 3),
+            ConflictCheckResult::Conflict
+        );
+        assert_eq!(pair(src, depth(1), 2, 3), ConflictCheckResult::Conflict);
+        assert_eq!(pair(src, depth(2), 2, 3), ConflictCheckResult::NonConflict);
+    }
+
+    #[test]
+    fn blanket_impl_makes_bound_empty() {
+        let src = "struct String;
+trait From<T> {}
+trait Display {}
+trait ToString {}
+impl<T: Display> ToString for T {}
+impl<T: Display> From<T> for String {}
+impl<T: Display - ToString> From<T> for String {}
+";
+        let options = CheckOptions {
+            blanket_depth: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            pair(src, Default::default(), 1, 2),
+            ConflictCheckResult::Conflict
+        );
+        assert_eq!(pair(src, options, 1, 2), ConflictCheckResult::NonConflict);
+    }
+
+    #[test]
+    fn dead_impl_same_trait() {
+        let src = "trait A {}
+trait Tr {}
+impl<T: A - A> Tr for T {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec![
+                "warning: impl<T: A - A> Tr for T never applies: T: A implies A, which is excluded"
+            ]
+        );
+    }
+
+    #[test]
+    fn dead_impl_supertrait() {
+        let src = "trait Super {}
+trait Sub: Super {}
+trait Tr {}
+impl<T: Sub - Super> Tr for T {}
+impl<T: Sub> Tr for T {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec![
+                "warning: impl<T: Sub - Super> Tr for T never applies: T: Sub implies Super, which is excluded",
+                "warning: impl<T: Sub - Super> Tr for T is shadowed by impl<T: Sub> Tr for T",
+            ]
+        );
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn satisfiable_impl_not_reported() {
+        let src = "trait Super {}
+trait Sub: Super {}
+trait Tr {}
+impl<T: Super - Sub> Tr for T {}
+";
+        assert!(diagnostics(src, Default::default()).is_empty());
+    }
+
+    #[test]
+    fn overlap_drops_implied_positive() {
+        let src = "trait Super {}
+trait Sub: Super {}
+trait Tr {}
+impl<T: Sub> Tr for T {}
+impl<T: Super> Tr for T {}
+";
+        assert_eq!(overlaps(src, Default::default(), 0, 1), vec!["T': Sub"]);
+    }
+
+    #[test]
+    fn overlap_drops_implied_negative() {
+        let src = "trait Super {}
+trait Sub: Super {}
+trait A {}
+trait Tr {}
+impl<T: A - Sub> Tr for T {}
+impl<T: A - Super> Tr for T {}
+";
+        assert_eq!(
+            overlaps(src, Default::default(), 0, 1),
+            vec!["T': A - Super"]
+        );
+    }
+
+    #[test]
+    fn shadowed_by_generic_header() {
+        let src = "struct Vec<T>;
+struct u8;
+trait Tr {}
+impl<T> Tr for Vec<T> {}
+impl Tr for Vec<u8> {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec!["warning: impl Tr for Vec<u8> is shadowed by impl<T> Tr for Vec<T>"]
+        );
+    }
+
+    #[test]
+    fn shadowed_by_weaker_bound() {
+        let src = "trait Super {}
+trait Sub: Super {}
+trait Other {}
+trait Tr {}
+impl<T: Super> Tr for T {}
+impl<T: Sub> Tr for T {}
+impl<T: Other> Tr for T {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec!["warning: impl<T: Sub> Tr for T is shadowed by impl<T: Super> Tr for T"]
+        );
+    }
+
+    #[test]
+    fn equivalent_impls_report_later_one() {
+        let src = "trait A {}
+trait Tr {}
+impl<T: A> Tr for T {}
+impl<U: A> Tr for U {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec!["warning: impl<U: A> Tr for U is shadowed by impl<T: A> Tr for T"]
+        );
+    }
+
+    #[test]
+    fn trait_param_bound_obligations() {
+        let src = "struct u8;
+trait X {}
+trait From<T: X> {}
+struct S;
+impl From<u8> for S {}
+impl<T> From<T> for u8 {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec![
+                "note: impl From<u8> for S must prove u8: X required by From<u8>",
+                "note: impl<T> From<T> for u8 must prove T: X required by From<T>",
+            ]
+        );
+        assert_eq!(
+            diagnostics(src, closed_world()),
+            vec![
+                "error: impl From<u8> for S is ill-formed: From<u8> requires u8: X, which does not hold",
+                "note: impl<T> From<T> for u8 must prove T: X required by From<T>",
+            ]
+        );
+    }
+
+    #[test]
+    fn struct_param_bound_obligations() {
+        let src = "struct u8;
+trait A {}
+trait Tr {}
+struct S<T: A>;
+impl<T> Tr for S<T> {}
+impl Tr for S<u8> {}
+";
+        assert!(diagnostics(src, Default::default())
+            .contains(&"note: impl Tr for S<u8> must prove u8: A required by S<u8>".to_string()));
+    }
+
+    #[test]
+    fn formula_bound_obligations() {
+        let src = "struct u8;
+trait A {}
+trait B {}
+trait Tr {}
+struct S<T: (A | B)>;
+impl<T: A> Tr for S<T> {}
+impl Tr for S<u8> {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec!["note: impl Tr for S<u8> must prove u8: (A | B) required by S<u8>"]
+        );
+        assert_eq!(
+            diagnostics(src, closed_world()),
+            vec!["error: impl Tr for S<u8> is ill-formed: S<u8> requires u8: (A | B), which does not hold"]
+        );
+    }
+
+    #[test]
+    fn implied_bounds_decide_disjointness() {
+        let src = "trait A {}
+trait B {}
+trait Tr {}
+struct Wrap<T: A - B>;
+impl<T> Tr for Wrap<T> {}
+impl<T: B> Tr for Wrap<T> {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn implied_bounds_do_not_discharge_obligations() {
+        let src = "struct u8;
+trait A {}
+trait Tr {}
+struct S<T: A>;
+impl<T> Tr for S<T> {}
+impl Tr for S<u8> {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec![
+                "note: impl<T> Tr for S<T> must prove T: A required by S<T>",
+                "note: impl Tr for S<u8> must prove u8: A required by S<u8>",
+            ]
+        );
+    }
+
+    #[test]
+    fn implied_bounds_do_not_make_impl_dead() {
+        let src = "trait A {}
+trait B {}
+trait Tr {}
+struct W<T: A>;
+impl<T: B - A> Tr for W<T> {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec!["error: impl<T: B - A> Tr for W<T> is ill-formed: W<T> requires T: A, which does not hold"]
+        );
+    }
+
+    #[test]
+    fn unconstrained_param_reported() {
+        let src = include_str!("../../sample4.rs");
+        let diags = diagnostics(src, Default::default());
+        assert_eq!(
+            diags[0],
+            "error: S in impl<S: AsRef<str>, I: IntoIterator<S>> From<I> for A is not constrained by the trait or self type"
+        );
+        assert_eq!(diags.len(), 3);
+    }
+
+    #[test]
+    fn existential_instances_decide_disjointness() {
+        let src = "struct u8;
+trait A {}
+trait B<T> {}
+trait Tr {}
+impl A for u8 {}
+impl<S: A, T: B<S>> Tr for T {}
+impl<T: - B<u8>> Tr for T {}
+";
+        let existential = CheckOptions {
+            existential: true,
+            ..closed_world()
+        };
+        assert_eq!(
+            pair(src, closed_world(), 1, 2),
+            ConflictCheckResult::Conflict
+        );
+        assert_eq!(
+            pair(src, existential, 1, 2),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn existential_route_shown_in_open_world() {
+        let src = "trait A {}
+trait B<T> {}
+trait Tr {}
+impl<S: A, T: B<S>> Tr for T {}
+impl<T: B<T>> Tr for T {}
+";
+        let existential = CheckOptions {
+            existential: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            overlaps(src, existential, 0, 1),
+            vec!["T': B<S> + B<T'>", "exists S: A"]
+        );
+    }
+
+    #[test]
+    fn param_shadowing_struct_is_generic() {
+        let src = "struct u8;
+struct u16;
+trait Tr {}
+impl<u8> Tr for u8 {}
+impl Tr for u16 {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::Conflict
+        );
+    }
+
+    const NEGATIVE_SUPERTRAIT: &str = "struct u8;
+trait Bar {}
+trait Baz {}
+trait Foo: Bar - Baz {}
+trait Tr {}
+impl<T: Foo> Tr for T {}
+impl<T: Baz> Tr for T {}
+impl<T: Foo + Baz> Bar for T {}
+impl Baz for u8 {}
+impl Foo for u8 {}
+";
+
+    #[test]
+    fn negative_supertrait_excludes() {
+        assert_eq!(
+            pair(NEGATIVE_SUPERTRAIT, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn negative_supertrait_diagnostics() {
+        assert_eq!(
+            diagnostics(NEGATIVE_SUPERTRAIT, Default::default()),
+            vec![
+                "warning: impl<T: Foo + Baz> Bar for T never applies: T: Foo excludes Baz",
+                "error: impl Foo for u8 is ill-formed: Foo requires u8: !Baz, which does not hold",
+            ]
+        );
+    }
+
+    #[test]
+    fn routes_bound_to_same_type_checked_together() {
+        let src = "struct u8;
+struct Pair<T, U>;
+trait A {}
+trait Tr {}
+impl<T: A> Tr for Pair<T, u8> {}
+impl<U: - A> Tr for Pair<u8, U> {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    const PREDICATES: &str = "struct u8;
+struct u16;
+struct Vec<T>;
+struct Pair<T, U>;
+trait A {}
+trait Tr {}
+impl<T> Tr for T where T != u8 {}
+impl Tr for u8 {}
+impl<T, U> Tr for Pair<T, U> where T == U {}
+impl Tr for Pair<u8, u16> {}
+impl<T> Tr for Vec<T> where T != u8 {}
+impl<U> Tr for Vec<U> {}
+";
+
+    #[test]
+    fn disequality_separates_impls() {
+        assert_eq!(
+            pair(PREDICATES, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+        assert_eq!(
+            pair(PREDICATES, Default::default(), 4, 5),
+            ConflictCheckResult::Conflict
+        );
+    }
+
+    #[test]
+    fn equality_unified_before_bounds() {
+        assert_eq!(
+            pair(PREDICATES, Default::default(), 2, 3),
+            ConflictCheckResult::NonConflict
+        );
+        let src = "struct u8;
+struct Pair<T, U>;
+trait A {}
+trait Tr {}
+impl<T: A, U> Tr for Pair<T, U> where T == U {}
+impl<V: - A> Tr for Pair<u8, V> {}
+";
+        assert_eq!(
+            pair(src, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    const WHERE_CLAUSES: &str = "struct Vec<T>;
+trait Clone {}
+trait Copy {}
+trait A {}
+trait B {}
+trait Tr {}
+trait S where Self: B {}
+struct W<T> where T: A;
+impl<T> Tr for T where Vec<T>: Clone - Copy {}
+impl<T> Tr for T where Vec<T>: Copy {}
+impl<T: S> Tr for T {}
+impl<T: - B> Tr for T {}
+impl<T> Clone for W<T> {}
+impl<T> Copy for W<T> where T: - A {}
+";
+
+    #[test]
+    fn bounds_on_compound_types() {
+        assert_eq!(
+            pair(WHERE_CLAUSES, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+        assert_eq!(
+            overlaps(WHERE_CLAUSES, Default::default(), 0, 2),
+            vec!["T': S", "Vec<T'>: Clone - Copy"]
+        );
+    }
+
+    #[test]
+    fn trait_where_self_is_supertrait() {
+        assert_eq!(
+            pair(WHERE_CLAUSES, Default::default(), 2, 3),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn struct_where_clause_obligations() {
+        assert_eq!(
+            diagnostics(WHERE_CLAUSES, Default::default()),
+            vec![
+                "note: impl<T> Clone for W<T> must prove T: A required by W<T>",
+                "error: impl<T> Copy for W<T> where T: -A is ill-formed: W<T> requires T: A, which does not hold",
+            ]
+        );
+    }
+
+    #[test]
+    fn param_order_does_not_matter() {
+        let decls = "struct A;
+struct str;
+trait From<T> {}
+trait IntoIterator<I> {}
+trait AsRef<T> {}
+";
+        let forward = format!(
+            "{}impl<I: IntoIterator<S>, S: AsRef<str>> From<I> for A {{}}\nimpl<I: - IntoIterator<str>> From<I> for A {{}}\n",
+            decls
+        );
+        let backward = format!(
+            "{}impl<S: AsRef<str>, I: IntoIterator<S>> From<I> for A {{}}\nimpl<I: - IntoIterator<str>> From<I> for A {{}}\n",
+            decls
+        );
+        let existential = CheckOptions {
+            existential: true,
+            ..Default::default()
+        };
+        for options in [Default::default(), existential] {
+            assert_eq!(
+                overlaps(&forward, options.clone(), 0, 1),
+                overlaps(&backward, options.clone(), 0, 1)
+            );
+            assert_eq!(
+                diagnostics(&forward, options.clone()).len(),
+                diagnostics(&backward, options).len()
+            );
+        }
+        assert_eq!(
+            overlaps(&forward, Default::default(), 0, 1),
+            vec!["I': IntoIterator<S> - IntoIterator<str>"]
+        );
+    }
+
+    const ORD: &str = "struct u8;
+trait Eq {}
+trait PartialOrd<Rhs> {}
+trait Ord: Eq + PartialOrd<Self> {}
+trait Tr {}
+impl<T: Ord> Tr for T {}
+impl<T: - PartialOrd<T>> Tr for T {}
+impl<T: - PartialOrd<u8>> Tr for T {}
+impl Ord for u8 {}
+";
+
+    #[test]
+    fn sup_substitutes_self() {
+        let (checker, _) = check_src(ORD, Default::default());
+        let mut env = ConflictCheckEnv::new(&checker);
+        let ci = env.get_concrete_impl(&checker.impls[3]).unwrap();
+        let mut sup: Vec<_> = env
+            .sup(&ci.impl_for, &ci.trait_exp)
+            .unwrap()
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        sup.sort();
+        assert_eq!(sup, vec!["Eq", "PartialOrd<u8>"]);
+    }
+
+    #[test]
+    fn self_in_supertrait_decides_overlap() {
+        assert_eq!(
+            pair(ORD, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+        assert_eq!(
+            pair(ORD, Default::default(), 0, 2),
+            ConflictCheckResult::Conflict
+        );
+    }
+
+    #[test]
+    fn defaulted_trait_param_same_header() {
+        let src = "struct A;
+trait Add<Rhs = Self> {}
+impl Add for A {}
+impl Add<A> for A {}
+";
+        let res = pair_result(src, Default::default(), 0, 1);
+        assert_eq!(res.0, ConflictCheckResult::Conflict);
+        assert_eq!(res.1.to_string(), "impl Add for A");
+        assert_eq!(res.2.to_string(), "impl Add<A> for A");
+    }
+
+    #[test]
+    fn diagnostics_keep_source_form() {
+        let src = "struct u8;
+struct S<T = u8>;
+trait Tr {}
+impl Tr for S {}
+impl Tr for S<u8> {}
+";
+        assert_eq!(
+            diagnostics(src, Default::default()),
+            vec!["warning: impl Tr for S<u8> is shadowed by impl Tr for S"]
+        );
+    }
+
+    const ASSOC: &str = "struct u8;
+struct u16;
+struct V;
+struct Vec<T>;
+trait Iterator { type Item; }
+trait Tr {}
+impl<T: Iterator<Item = u8>> Tr for T {}
+impl<T: Iterator<Item = u16>> Tr for T {}
+impl<T: Iterator<Item = u8>> Tr for T where <T as Iterator>::Item == u16 {}
+impl Iterator for V { type Item = u16; }
+impl<X> Iterator for Vec<X> { type Item = X; }
+impl Tr for V {}
+impl Tr for Vec<u8> {}
+";
+
+    #[test]
+    fn assoc_equalities_separate_impls() {
+        assert_eq!(
+            pair(ASSOC, Default::default(), 0, 1),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn assoc_definitions_decide_projections() {
+        assert_eq!(
+            pair(ASSOC, Default::default(), 0, 5),
+            ConflictCheckResult::NonConflict
+        );
+        assert_eq!(
+            pair(ASSOC, Default::default(), 1, 5),
+            ConflictCheckResult::Conflict
+        );
+        assert_eq!(
+            pair(ASSOC, Default::default(), 0, 6),
+            ConflictCheckResult::Conflict
+        );
+        assert_eq!(
+            pair(ASSOC, Default::default(), 1, 6),
+            ConflictCheckResult::NonConflict
+        );
+    }
+
+    #[test]
+    fn assoc_mismatch_reported() {
+        assert_eq!(
+            diagnostics(ASSOC, Default::default()),
+            vec!["warning: impl<T: Iterator<Item = u8>> Tr for T where <T as Iterator>::Item == u16 never applies: where clause cannot hold: u8 and u16 mismatch"]
+        );
+    }
+
+    #[test]
+    fn projections_scoped_to_impl() {
+        let (checker, _) = check_src(ASSOC, Default::default());
+        let mut env = ConflictCheckEnv::new(&checker);
+        let c0 = env.get_concrete_impl(&checker.impls[0]).unwrap();
+        let c2 = env.get_concrete_impl(&checker.impls[2]).unwrap();
+        assert_eq!(c0.projections.len(), 1);
+        assert_eq!(c2.projections.len(), 1);
+        assert_ne!(c0.projections[0].value, c2.projections[0].value);
+        assert!(env.projections.is_empty());
+    }
 }
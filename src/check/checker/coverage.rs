@@ -0,0 +1,439 @@
+use super::*;
+
+/// 各ヘッダ型変数の境界の組。全ての型変数の境界を満たす型の組の集合を表す
+type Cube = Vec<ConcreteBound>;
+
+/// 同じヘッダを持つ impl の族の網羅性の検査結果
+#[derive(Debug, Clone)]
+pub struct CoverageReport {
+    header: String,
+    impls: Vec<String>,
+    overlaps: Vec<(String, String)>,
+    gaps: Vec<String>,
+    excess: Vec<(String, String)>,
+    /// Cube で表せない impl と、その理由
+    skipped: Vec<(String, &'static str)>,
+}
+impl Display for CoverageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Coverage of {}:", self.header)?;
+        for i in &self.impls {
+            writeln!(f, "  {}", i)?;
+        }
+        for (i1, i2) in &self.overlaps {
+            writeln!(f, "  overlap: {} and {}", i1, i2)?;
+        }
+        for g in &self.gaps {
+            writeln!(f, "  gap: {}", g)?;
+        }
+        for (i, e) in &self.excess {
+            writeln!(f, "  outside reference: {} covers {}", i, e)?;
+        }
+        for (i, reason) in &self.skipped {
+            writeln!(f, "  skipped ({}): {}", reason, i)?;
+        }
+        if self.overlaps.is_empty() && self.gaps.is_empty() && self.excess.is_empty() {
+            writeln!(f, "  complete")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ConflictCheckEnv<'a> {
+    /// ヘッダが型変数の名前の付け替えを除いて等しいか
+    fn same_header(checker: &'a Checker, i1: &Impl, i2: &Impl) -> Result<bool> {
+        let mut env = Self::new(checker);
+        let c1 = env.get_concrete_impl(i1)?;
+        let c2 = env.get_concrete_impl(i2)?;
+        let matches = |pat: &ConcreteImpl, target: &ConcreteImpl| {
+            let mut unif = Unifier::new();
+            unif.trait_match(&pat.trait_exp, &target.trait_exp).is_ok()
+                && unif.type_match(&pat.impl_for, &target.impl_for).is_ok()
+        };
+        Ok(matches(&c1, &c2) && matches(&c2, &c1))
+    }
+
+    /// `tys` は Cube の各成分に対応するヘッダ型変数。満たせない成分があれば空
+    fn is_empty_cube(&self, tys: &[ConcreteType], cube: &[ConcreteBound]) -> Result<bool> {
+        for (ty, b) in tys.iter().zip(cube) {
+            if self.unsatisfiable(ty, b)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    /// `r` から `c` を除いた領域を、互いに素な Cube の和として求める
+    fn subtract(
+        &self,
+        tys: &[ConcreteType],
+        r: &[ConcreteBound],
+        c: &[ConcreteBound],
+    ) -> Result<Vec<Cube>> {
+        fn sorted(ts: &HashSet<ConcreteTrait>) -> Vec<ConcreteTrait> {
+            let mut ts: Vec<_> = ts.iter().cloned().collect();
+            ts.sort_by_key(|t| t.to_string());
+            ts
+        }
+        let inter: Cube = r
+            .iter()
+            .zip(c)
+            .map(|(x, y)| ConcreteBound::join(x, y))
+            .collect();
+        if self.is_empty_cube(tys, &inter)? {
+            return Ok(vec![r.to_vec()]);
+        }
+        let mut res = Vec::new();
+        let mut cur = r.to_vec();
+        for k in 0..r.len() {
+            for p in sorted(&c[k].pos) {
+                if self.a(&tys[k], &cur[k])?.contains(&p) {
+                    continue;
+                }
+                let mut piece = cur.clone();
+                piece[k].neg.insert(p.clone());
+                if !self.is_empty_cube(tys, &piece)? {
+                    res.push(piece);
+                }
+                cur[k].pos.insert(p);
+            }
+            for n in sorted(&c[k].neg) {
+                if cur[k].neg.contains(&n) {
+                    continue;
+                }
+                let mut piece = cur.clone();
+                piece[k].pos.insert(n.clone());
+                if !self.is_empty_cube(tys, &piece)? {
+                    res.push(piece);
+                }
+                cur[k].neg.insert(n);
+            }
+        }
+        Ok(res)
+    }
+    fn display_cube(
+        &self,
+        names: &[String],
+        tys: &[ConcreteType],
+        cube: &[ConcreteBound],
+    ) -> Result<String> {
+        let mut parts = Vec::new();
+        for ((name, ty), b) in names.iter().zip(tys).zip(cube) {
+            let b = self.normalize(ty, b)?;
+            if b.pos.is_empty() && b.neg.is_empty() {
+                parts.push(name.clone());
+            } else {
+                parts.push(format!("{}: {}", name, b));
+            }
+        }
+        Ok(parts.join(", "))
+    }
+
+    /// 同じヘッダを持つ impl の族について、互いに素か、参照する境界を覆っているかを調べる
+    ///
+    /// `reference` が空なら、各 impl から負の境界を除いたものの和を参照する。
+    /// ヘッダに無い型変数か論理式の境界を含む `reference` はエラーとする
+    fn coverage(
+        checker: &'a Checker,
+        family: &[&Impl],
+        reference: &[Param],
+    ) -> Result<CoverageReport> {
+        let first = family[0];
+        let mut env = Self::new(checker);
+        let h0 = env.get_concrete_impl(first)?;
+        let header_params: Vec<_> = (0..env.params.len())
+            .filter(|id| {
+                h0.impl_for.contains_param(*id)
+                    || h0.trait_exp.params.iter().any(|p| p.contains_param(*id))
+            })
+            .collect();
+        let names: Vec<_> = header_params
+            .iter()
+            .map(|id| env.param_names[*id].clone())
+            .collect();
+        let tys: Vec<_> = header_params.iter().map(|id| env.param_ty(*id)).collect();
+        let mut cubes = Vec::new();
+        let mut members = Vec::new();
+        let mut skipped = Vec::new();
+        for im in family {
+            let base = env.params.len();
+            let ci = env.get_concrete_impl(im)?;
+            let mut unif = Unifier::new();
+            if !unif.trait_match(&ci.trait_exp, &h0.trait_exp).is_ok()
+                || !unif.type_match(&ci.impl_for, &h0.impl_for).is_ok()
+            {
+                return Err(anyhow!("Header of {} differs from {}", im, first));
+            }
+            let mut cube = vec![ConcreteBound::default(); header_params.len()];
+            let mut constrained = true;
+            for id in base..env.params.len() {
+                let p = ConcreteType::Param {
+                    id,
+                    name: String::new(),
+                };
+                let k = match unif.resolve(&p) {
+                    ConcreteType::Param { id: k, .. } => header_params.iter().position(|h| *h == k),
+                    ConcreteType::Type { .. } => None,
+                };
+                match (k, &env.params[id]) {
+                    (Some(k), Some(b)) => {
+                        cube[k] = ConcreteBound::join(&cube[k], &unif.resolve_bound(b));
+                    }
+                    (Some(_), None) => {}
+                    (None, _) => constrained = false,
+                }
+            }
+            if !constrained {
+                skipped.push((im.to_string(), "unconstrained params"));
+            } else if cube.iter().any(|b| !b.formulas.is_empty()) {
+                skipped.push((im.to_string(), "bound formulas"));
+            } else if ci.has_predicates() {
+                skipped.push((im.to_string(), "where predicates"));
+            } else {
+                cubes.push(cube);
+                members.push(*im);
+            }
+        }
+        env.checked_params = env.params.len();
+        if checker.options.blanket_depth.is_some() {
+            env.lower_blankets()?;
+        }
+        for p in reference {
+            if !names.contains(&p.name) {
+                return Err(anyhow!(
+                    "Reference param {} is not a header param of {}",
+                    p.name,
+                    first
+                ));
+            }
+            if p.bound.iter().any(|b| !b.formulas.is_empty()) {
+                return Err(anyhow!(
+                    "Reference bound {} has formulas, which coverage cannot represent",
+                    p
+                ));
+            }
+        }
+
+        let references = if reference.is_empty() {
+            cubes
+                .iter()
+                .map(|c| {
+                    c.iter()
+                        .map(|b| ConcreteBound {
+                            pos: b.pos.clone(),
+                            ..Default::default()
+                        })
+                        .collect()
+                })
+                .collect()
+        } else {
+            // 参照の型変数を、同名のヘッダの型変数に置き換える
+            let positions: Vec<_> = reference
+                .iter()
+                .map(|p| names.iter().position(|n| *n == p.name).unwrap())
+                .collect();
+            let args: Vec<_> = positions
+                .iter()
+                .map(|k| ConcreteType::Param {
+                    id: header_params[*k],
+                    name: names[*k].clone(),
+                })
+                .collect();
+            let mut cube = vec![ConcreteBound::default(); header_params.len()];
+            for (p, k) in reference.iter().zip(positions) {
+                if let Some(b) = &p.bound {
+                    cube[k] = ConcreteBound::join(&cube[k], &env.lower_bound(&args, b)?);
+                }
+            }
+            vec![cube]
+        };
+
+        let mut gaps = references.clone();
+        for c in &cubes {
+            let mut rest = Vec::new();
+            for g in &gaps {
+                rest.extend(env.subtract(&tys, g, c)?);
+            }
+            gaps = rest;
+        }
+        let mut gap_strs = Vec::new();
+        for g in &gaps {
+            let s = env.display_cube(&names, &tys, g)?;
+            if !gap_strs.contains(&s) {
+                gap_strs.push(s);
+            }
+        }
+        let mut excess = Vec::new();
+        if !reference.is_empty() {
+            for (im, c) in members.iter().zip(&cubes) {
+                for e in env.subtract(&tys, c, &references[0])? {
+                    excess.push((im.to_string(), env.display_cube(&names, &tys, &e)?));
+                }
+            }
+        }
+
+        let mut overlaps = Vec::new();
+        for i in 0..members.len() {
+            for j in (i + 1)..members.len() {
+                let (res, _) = Self::check_impls(checker, members[i], members[j])?;
+                if res == ConflictCheckResult::Conflict {
+                    overlaps.push((members[i].to_string(), members[j].to_string()));
+                }
+            }
+        }
+
+        Ok(CoverageReport {
+            header: format!("{} for {}", first.trait_exp, first.impl_for),
+            impls: members.iter().map(|i| i.to_string()).collect(),
+            overlaps,
+            gaps: gap_strs,
+            excess,
+            skipped,
+        })
+    }
+}
+
+impl Checker {
+    /// トレイト `trait_name` の impl を、ヘッダが等しいものごとに網羅性を検査する
+    pub fn coverage(&self, trait_name: &str, reference: &[Param]) -> Result<Vec<CoverageReport>> {
+        // 名前で探すのは利用者が与えたトレイトだけで、impl とは番号で比べる
+        let id = self
+            .traits
+            .iter()
+            .position(|t| t.name == trait_name)
+            .ok_or_else(|| anyhow!("Undefined trait {}", trait_name))?;
+        let mut families: Vec<Vec<&Impl>> = Vec::new();
+        for im in self.impls.iter().filter(|i| i.trait_exp.id.0 == id) {
+            let mut found = false;
+            for family in families.iter_mut() {
+                if ConflictCheckEnv::same_header(self, family[0], im)? {
+                    family.push(im);
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                families.push(vec![im]);
+            }
+        }
+        families
+            .iter()
+            .map(|family| {
+                ConflictCheckEnv::coverage(self, family, reference)
+                    .with_context(|| format!("coverage of {} check error", family[0]))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::check_src;
+    use super::*;
+
+    /// `reference` は `--reference` と同じ書式
+    fn coverage(src: &str, trait_name: &str, reference: &[&str]) -> Result<String> {
+        let (checker, _) = check_src(src, Default::default());
+        let program = parser::program(src).unwrap().1;
+        let reference: Vec<_> = reference
+            .iter()
+            .map(|r| parser::param(parser::extend_trait_bound)(r).unwrap().1)
+            .collect();
+        let reference = names::lower_params(&program, &reference).unwrap();
+        let reports = checker.coverage(trait_name, &reference)?;
+        Ok(reports.iter().map(|r| r.to_string()).collect())
+    }
+
+    const DECLS: &str = "struct u8;
+struct u16;
+struct A;
+trait From<I> {}
+trait IntoIterator<I> {}
+";
+
+    #[test]
+    fn partition_is_complete() {
+        let src = "struct String;
+trait From<T> {}
+trait Display {}
+trait ToString {}
+impl<T: Display> From<T> for String {}
+impl<T: ToString - Display> From<T> for String {}
+";
+        assert_eq!(
+            coverage(src, "From", &[]).unwrap(),
+            "Coverage of From<T> for String:
+  impl<T: Display> From<T> for String
+  impl<T: ToString - Display> From<T> for String
+  complete
+"
+        );
+    }
+
+    #[test]
+    fn overlap_and_gap_against_reference() {
+        let src = format!(
+            "{}impl<I: IntoIterator<u8>> From<I> for A {{}}
+impl<I: IntoIterator<u8> + IntoIterator<u16>> From<I> for A {{}}
+impl<I: IntoIterator<u16> - IntoIterator<u8>> From<I> for A {{}}
+",
+            DECLS
+        );
+        let report = coverage(&src, "From", &["I: IntoIterator<u8>"]).unwrap();
+        assert!(report.contains(
+            "  overlap: impl<I: IntoIterator<u8>> From<I> for A and impl<I: IntoIterator<u8> + IntoIterator<u16>> From<I> for A\n"
+        ));
+        assert!(report.contains(
+            "  outside reference: impl<I: IntoIterator<u16> - IntoIterator<u8>> From<I> for A covers I: IntoIterator<u16> - IntoIterator<u8>\n"
+        ));
+        let report = coverage(&src, "From", &["I: IntoIterator<u8> + IntoIterator<u16>"]).unwrap();
+        assert!(!report.contains("gap"));
+        let report = coverage(&src, "From", &["I"]).unwrap();
+        assert!(report.contains("  gap: I: -IntoIterator<u16> - IntoIterator<u8>\n"));
+    }
+
+    #[test]
+    fn gap_reported() {
+        let src = format!(
+            "{}impl<I: IntoIterator<u8> - IntoIterator<u16>> From<I> for A {{}}
+impl<I: IntoIterator<u16> - IntoIterator<u8>> From<I> for A {{}}
+",
+            DECLS
+        );
+        let report = coverage(&src, "From", &["I: IntoIterator<u8>"]).unwrap();
+        assert!(report.contains("  gap: I: IntoIterator<u16> + IntoIterator<u8>\n"));
+    }
+
+    #[test]
+    fn excluded_region_not_a_gap() {
+        let src = "struct A;
+trait From<I> {}
+trait C {}
+trait B: - C {}
+impl<I: - C> From<I> for A {}
+impl<I: C - B> From<I> for A {}
+";
+        assert!(coverage(src, "From", &[]).unwrap().contains("  complete\n"));
+    }
+
+    #[test]
+    fn reference_param_not_in_header() {
+        let src = format!("{}impl<I: IntoIterator<u8>> From<I> for A {{}}\n", DECLS);
+        let err = coverage(&src, "From", &["X: IntoIterator<u8>"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("Reference param X is not a header param"));
+    }
+
+    #[test]
+    fn unknown_trait_rejected() {
+        let src = format!("{}impl<I: IntoIterator<u8>> From<I> for A {{}}\n", DECLS);
+        let err = coverage(&src, "Into", &[]).unwrap_err();
+        assert!(format!("{:#}", err).contains("Undefined trait Into"));
+    }
+
+    #[test]
+    fn reference_with_formulas() {
+        let src = format!("{}impl<I: IntoIterator<u8>> From<I> for A {{}}\n", DECLS);
+        let err =
+            coverage(&src, "From", &["I: (IntoIterator<u8> | IntoIterator<u16>)"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("has formulas"));
+    }
+}
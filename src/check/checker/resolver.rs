@@ -0,0 +1,441 @@
+use super::*;
+
+/// 候補がこれより多い場合は最小の組み合わせを探さず貪欲に選ぶ
+const EXACT_SEARCH_LIMIT: usize = 16;
+
+/// impl に加える編集
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Edit {
+    /// 型変数に負の境界を一つ加える
+    Neg {
+        impl_index: usize,
+        param: String,
+        neg: TExp,
+    },
+    /// 型変数が型と等しくないという条件を where 節に加える
+    Ne {
+        impl_index: usize,
+        param: String,
+        ty: TExp,
+    },
+}
+impl Edit {
+    fn impl_index(&self) -> usize {
+        match self {
+            Edit::Neg { impl_index, .. } | Edit::Ne { impl_index, .. } => *impl_index,
+        }
+    }
+}
+impl Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edit::Neg { param, neg, .. } => write!(f, "{} - {}", param, neg),
+            Edit::Ne { param, ty, .. } => write!(f, "where {} != {}", param, ty),
+        }
+    }
+}
+
+/// 衝突の解消結果
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// 書き換えたプログラム
+    pub program: Program,
+    /// 行った編集
+    pub edits: Vec<String>,
+    /// 他の impl に覆われているので取り除いた impl と、覆う impl
+    pub removed: Vec<(Impl, Impl)>,
+}
+
+/// 具体的な型を、`names` にある型変数だけを使った型式に戻す
+fn type_to_texp(ct: &ConcreteType, names: &HashMap<usize, String>) -> Option<TExp> {
+    match ct {
+        ConcreteType::Type { name, params, .. } => Some(TExp {
+            name: name.clone(),
+            params: params
+                .iter()
+                .map(|p| type_to_texp(p, names))
+                .collect::<Option<_>>()?,
+            ..Default::default()
+        }),
+        ConcreteType::Param { id, .. } => names.get(id).map(|name| TExp {
+            name: name.clone(),
+            ..Default::default()
+        }),
+    }
+}
+
+impl<'a> ConflictCheckEnv<'a> {
+    /// 衝突する impl の組を解消する編集の候補。どれか一つを行えば解消する
+    ///
+    /// 一方の impl の型変数に、もう一方の境界の A(b) に含まれるトレイトを負の境界として加える
+    fn conflict_edits(
+        checker: &'a Checker,
+        (n1, i1): (usize, &Impl),
+        (n2, i2): (usize, &Impl),
+    ) -> Result<HashSet<Edit>> {
+        let mut env = Self::new(checker);
+        let c1 = env.get_concrete_impl(i1)?;
+        let mid = env.params.len();
+        let c2 = env.get_concrete_impl(i2)?;
+        env.checked_params = env.params.len();
+        if checker.options.blanket_depth.is_some() {
+            env.lower_blankets()?;
+        }
+        let mut edits = HashSet::new();
+        let mut unif = Unifier::new();
+        if !Self::unify_headers(&mut unif, &c1, &c2)? {
+            return Ok(edits);
+        }
+        let sides = [(n1, i1, 0..mid), (n2, i2, mid..env.checked_params)];
+        // 型変数の名前を、単一化で同じになった型変数にも付ける
+        let names_of = |im: &Impl, range: &std::ops::Range<usize>| {
+            let mut names = HashMap::new();
+            // 射影を表す型変数は impl の型変数リストに無い
+            for id in range.clone().take(im.params.len()) {
+                let name = &im.params[id - range.start].name;
+                for x in unif.class_of(unif.root(id)) {
+                    names.insert(x, name.clone());
+                }
+            }
+            names
+        };
+        for (ct, ps) in unif.get_all_unified_params() {
+            // 型と単一化された型変数は、その型と等しくないという条件で除ける
+            if let Some(ct) = &ct {
+                for (index, im, range) in &sides {
+                    let Some(ty) = type_to_texp(ct, &names_of(im, range)) else {
+                        continue;
+                    };
+                    for q in ps.iter().filter(|q| range.contains(q)) {
+                        if let Some(param) = im.params.get(q - range.start) {
+                            edits.insert(Edit::Ne {
+                                impl_index: *index,
+                                param: param.name.clone(),
+                                ty: ty.clone(),
+                            });
+                        }
+                    }
+                }
+                continue;
+            }
+            let bound_of = |range: &std::ops::Range<usize>| {
+                let mut bound = ConcreteBound::default();
+                for p in ps.iter().filter(|p| range.contains(p)) {
+                    if let Some(b) = &env.params[*p] {
+                        bound = ConcreteBound::join(&bound, b);
+                    }
+                }
+                unif.resolve_bound(&bound)
+            };
+            for (own, other) in [(0, 1), (1, 0)] {
+                let (index, im, range) = &sides[own];
+                let own_bound = bound_of(range);
+                let self_ty = unif.resolve(&env.param_ty(*ps.iter().next().unwrap()));
+                let a_own = env.a(&self_ty, &own_bound)?;
+                let a_other = env.a(&self_ty, &bound_of(&sides[other].2))?;
+                let names = names_of(im, range);
+                for q in ps.iter().filter(|q| range.contains(q)) {
+                    let Some(param) = im.params.get(q - range.start) else {
+                        continue;
+                    };
+                    for t in &a_other {
+                        // 自身の A(b) に含まれるトレイトを除くと impl が適用されなくなる
+                        if a_own.contains(t) {
+                            continue;
+                        }
+                        let params = t
+                            .params
+                            .iter()
+                            .map(|p| type_to_texp(p, &names))
+                            .collect::<Option<_>>();
+                        if let Some(params) = params {
+                            edits.insert(Edit::Neg {
+                                impl_index: *index,
+                                param: param.name.clone(),
+                                neg: TExp {
+                                    name: t.name.clone(),
+                                    params,
+                                    ..Default::default()
+                                },
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(edits)
+    }
+}
+
+/// 全ての衝突を覆う最小の編集の組を選ぶ
+fn select_edits(conflicts: &[HashSet<Edit>]) -> Vec<Edit> {
+    let mut candidates: Vec<_> = conflicts.iter().flatten().cloned().collect();
+    candidates.sort_by_key(|e| (e.impl_index(), e.to_string()));
+    candidates.dedup();
+    let covers = |chosen: &[&Edit]| {
+        conflicts
+            .iter()
+            .all(|c| chosen.iter().any(|e| c.contains(*e)))
+    };
+    fn search<'e>(
+        candidates: &'e [Edit],
+        k: usize,
+        chosen: &mut Vec<&'e Edit>,
+        covers: &impl Fn(&[&Edit]) -> bool,
+    ) -> bool {
+        if k == 0 {
+            return covers(chosen);
+        }
+        for (i, e) in candidates.iter().enumerate() {
+            chosen.push(e);
+            if search(&candidates[i + 1..], k - 1, chosen, covers) {
+                return true;
+            }
+            chosen.pop();
+        }
+        false
+    }
+    if candidates.len() <= EXACT_SEARCH_LIMIT {
+        for k in 0..=conflicts.len() {
+            let mut chosen = Vec::new();
+            if search(&candidates, k, &mut chosen, &covers) {
+                return chosen.into_iter().cloned().collect();
+            }
+        }
+    }
+    // 最も多くの衝突を解消する編集から選ぶ
+    let mut rest: Vec<_> = conflicts.iter().collect();
+    let mut chosen = Vec::new();
+    while !rest.is_empty() {
+        let Some(best) = candidates
+            .iter()
+            .max_by_key(|e| rest.iter().filter(|c| c.contains(*e)).count())
+        else {
+            break;
+        };
+        rest.retain(|c| !c.contains(best));
+        chosen.push(best.clone());
+    }
+    chosen
+}
+
+/// 名前解決と整形式性の検査を行う。誤りがあればまとめて一つのエラーにする
+fn lower(program: &Program) -> Result<ir::Program> {
+    let (lowered, messages) = super::super::lower(program);
+    lowered.ok_or_else(|| anyhow!("{}", messages.join("\n")))
+}
+
+/// 衝突する impl の組に負の境界か where 節の不等式を加えて、衝突の無いプログラムに書き換える
+///
+/// どちらの編集でも解消できない組は、一方が他方に覆われていれば覆われている方を取り除く。
+/// 書き換えたプログラムに衝突が残ればエラーとする
+pub fn resolve(program: Program, options: CheckOptions) -> Result<Resolution> {
+    let mut checker = Checker::with_options(options.clone());
+    let results = checker.check(lower(&program)?)?;
+    let mut pairs = Vec::new();
+    let mut k = 0;
+    for i in 0..checker.impls.len() {
+        for j in (i + 1)..checker.impls.len() {
+            if results[k].0 == ConflictCheckResult::Conflict {
+                let i1 = &checker.impls[i];
+                let i2 = &checker.impls[j];
+                let edits = ConflictCheckEnv::conflict_edits(&checker, (i, i1), (j, i2))
+                    .with_context(|| format!("implementation {}, {} resolve error", i1, i2))?;
+                pairs.push((i, j, edits));
+            }
+            k += 1;
+        }
+    }
+    let mut removed: Vec<(usize, usize)> = Vec::new();
+    let mut unresolved = Vec::new();
+    for (i, j, edits) in &pairs {
+        let is_removed = |n: &usize| removed.iter().any(|(r, _)| r == n);
+        if !edits.is_empty() || is_removed(i) || is_removed(j) {
+            continue;
+        }
+        let (i1, i2) = (&checker.impls[*i], &checker.impls[*j]);
+        // 同等な impl の組は後のものを取り除く
+        if ConflictCheckEnv::subsumes(&checker, i1, i2)? {
+            removed.push((*j, *i));
+        } else if ConflictCheckEnv::subsumes(&checker, i2, i1)? {
+            removed.push((*i, *j));
+        } else {
+            unresolved.push((i1, i2));
+        }
+    }
+    if !unresolved.is_empty() {
+        let pairs: Vec<_> = unresolved
+            .iter()
+            .map(|(i1, i2)| format!("\n  {}\n  {}", i1, i2))
+            .collect();
+        return Err(anyhow!(
+            "Conflicts cannot be resolved by rewriting:{}",
+            pairs.concat()
+        ));
+    }
+    let conflicts: Vec<_> = pairs
+        .into_iter()
+        .filter(|(i, j, _)| !removed.iter().any(|(r, _)| r == i || r == j))
+        .map(|(_, _, edits)| edits)
+        .collect();
+    let edits = select_edits(&conflicts);
+
+    let Program(decls) = program;
+    let mut index = 0;
+    let mut rewritten = Vec::new();
+    for d in decls {
+        let Decl::Impl(mut im) = d else {
+            rewritten.push(d);
+            continue;
+        };
+        for e in edits.iter().filter(|e| e.impl_index() == index) {
+            match e {
+                Edit::Neg { param, neg, .. } => {
+                    let bound = im
+                        .params
+                        .iter_mut()
+                        .find(|p| p.name == *param)
+                        .map(|p| p.bound.get_or_insert_with(Default::default));
+                    if let Some(b) = bound {
+                        if !b.neg.contains(neg) {
+                            b.neg.push(neg.clone());
+                        }
+                    }
+                }
+                Edit::Ne { param, ty, .. } => {
+                    let param = TExp {
+                        name: param.clone(),
+                        ..Default::default()
+                    };
+                    im.predicates
+                        .push(super::super::Predicate::Ne(param, ty.clone()));
+                }
+            }
+        }
+        if !removed.iter().any(|(r, _)| *r == index) {
+            rewritten.push(Decl::Impl(im));
+        }
+        index += 1;
+    }
+    let program = Program(rewritten);
+
+    let mut recheck = Checker::with_options(options);
+    let remaining: Vec<_> = recheck
+        .check(lower(&program)?)?
+        .into_iter()
+        .filter(|r| r.0 == ConflictCheckResult::Conflict)
+        .map(|r| format!("\n  {}\n  {}", r.1, r.2))
+        .collect();
+    if !remaining.is_empty() {
+        return Err(anyhow!(
+            "Conflicts remain after rewriting:{}",
+            remaining.concat()
+        ));
+    }
+    Ok(Resolution {
+        program,
+        edits: edits
+            .iter()
+            .map(|e| format!("{}: {}", checker.impls[e.impl_index()], e))
+            .collect(),
+        removed: removed
+            .iter()
+            .map(|(r, by)| (checker.impls[*r].clone(), checker.impls[*by].clone()))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::check_src;
+    use super::*;
+
+    fn resolve_src(src: &str) -> Result<Resolution> {
+        resolve(parser::program(src).unwrap().1, Default::default())
+    }
+    /// 書き換えたプログラムを再検査して残った衝突の数
+    fn remaining(resolution: &Resolution) -> usize {
+        let (_, results) = check_src(&resolution.program.to_string(), Default::default());
+        results
+            .iter()
+            .filter(|r| r.0 == ConflictCheckResult::Conflict)
+            .count()
+    }
+
+    #[test]
+    fn samples_resolved() {
+        for src in [
+            include_str!("../../../presentation.rs"),
+            include_str!("../../../sample1.rs"),
+            include_str!("../../../sample2.rs"),
+            include_str!("../../../sample3.rs"),
+            include_str!("../../../sample5.rs"),
+            include_str!("../../../str_avoid.rs"),
+        ] {
+            let resolution = resolve_src(src).unwrap();
+            assert_eq!(remaining(&resolution), 0, "{}", resolution.program);
+        }
+    }
+
+    #[test]
+    fn shadowed_impl_removed() {
+        let resolution = resolve_src(include_str!("../../../presentation.rs")).unwrap();
+        assert_eq!(
+            resolution.program.to_string(),
+            "struct String;
+trait From<T> {}
+trait Display {}
+trait ToString {}
+impl<T: Display - ToString> From<T> for String {}
+impl<T: ToString> From<T> for String {}
+"
+        );
+        assert_eq!(resolution.removed.len(), 1);
+    }
+
+    #[test]
+    fn duplicate_impl_removed() {
+        let src = "trait A {}
+trait Tr {}
+impl<T: A> Tr for T {}
+impl<U: A> Tr for U {}
+";
+        let resolution = resolve_src(src).unwrap();
+        assert_eq!(remaining(&resolution), 0);
+        assert_eq!(
+            resolution.program.to_string(),
+            "trait A {}\ntrait Tr {}\nimpl<T: A> Tr for T {}\n"
+        );
+    }
+
+    #[test]
+    fn unbounded_param_gets_negative_bound() {
+        let src = "trait A {}
+trait Tr {}
+impl<T> Tr for T {}
+impl<T: A> Tr for T {}
+";
+        let resolution = resolve_src(src).unwrap();
+        assert_eq!(remaining(&resolution), 0);
+        assert_eq!(resolution.edits, vec!["impl<T> Tr for T: T - A"]);
+        assert_eq!(
+            resolution.program.to_string(),
+            "trait A {}\ntrait Tr {}\nimpl<T: -A> Tr for T {}\nimpl<T: A> Tr for T {}\n"
+        );
+    }
+
+    #[test]
+    fn concrete_route_gets_disequality() {
+        let resolution = resolve_src(include_str!("../../../sample3.rs")).unwrap();
+        assert_eq!(
+            resolution.edits,
+            vec!["impl<T: TA> From<T> for A: where T != A"]
+        );
+    }
+
+    #[test]
+    fn unresolvable_conflict_is_error() {
+        let err = resolve_src(include_str!("../../../sample4.rs")).unwrap_err();
+        assert!(err.to_string().starts_with("Conflicts cannot be resolved"));
+    }
+}
@@ -0,0 +1,388 @@
+use super::*;
+
+/// トレイトごとに、型がそれを実装するかの割り当て
+pub type Model = HashMap<ConcreteTrait, bool>;
+
+/// 部分的な割り当ての下での論理式の値。未定なら `None`
+fn eval(f: &Formula<usize>, assignment: &[Option<bool>]) -> Option<bool> {
+    match f {
+        Formula::Atom(v) => assignment[*v],
+        Formula::Not(g) => eval(g, assignment).map(|b| !b),
+        Formula::And(fs) => {
+            let mut res = Some(true);
+            for g in fs {
+                match eval(g, assignment) {
+                    Some(false) => return Some(false),
+                    None => res = None,
+                    Some(true) => {}
+                }
+            }
+            res
+        }
+        Formula::Or(fs) => {
+            let mut res = Some(false);
+            for g in fs {
+                match eval(g, assignment) {
+                    Some(true) => return Some(true),
+                    None => res = None,
+                    Some(false) => {}
+                }
+            }
+            res
+        }
+    }
+}
+
+/// 探索する節点の数の上限。超えたら判定できないとする
+const SEARCH_LIMIT: usize = 10_000;
+
+/// 割り当ての探索の結果
+#[derive(Debug)]
+pub enum Satisfy {
+    Yes(Model),
+    No,
+    /// 探索が上限に達した
+    Unknown,
+}
+
+/// 制約を満たす割り当ての探索
+struct Search<'c> {
+    constraints: &'c [Formula<usize>],
+    /// 制約ごとに現れる変数
+    vars: Vec<Vec<usize>>,
+    steps: usize,
+}
+impl<'c> Search<'c> {
+    fn new(constraints: &'c [Formula<usize>]) -> Self {
+        let vars = constraints
+            .iter()
+            .map(|c| {
+                let mut atoms = Vec::new();
+                c.atoms(&mut atoms);
+                let mut vars: Vec<_> = atoms.into_iter().cloned().collect();
+                vars.sort();
+                vars.dedup();
+                vars
+            })
+            .collect();
+        Self {
+            constraints,
+            vars,
+            steps: 0,
+        }
+    }
+
+    /// 未定の変数が一つだけの制約から、その変数の値を決める。偽になる制約があれば false
+    ///
+    /// 決めた変数は `trail` に積む
+    fn propagate(&self, assignment: &mut [Option<bool>], trail: &mut Vec<usize>) -> bool {
+        loop {
+            let mut changed = false;
+            for (c, vars) in self.constraints.iter().zip(&self.vars) {
+                match eval(c, assignment) {
+                    Some(true) => continue,
+                    Some(false) => return false,
+                    None => {}
+                }
+                let mut free = vars.iter().filter(|v| assignment[**v].is_none());
+                let (Some(&v), None) = (free.next(), free.next()) else {
+                    continue;
+                };
+                let holds = [false, true].map(|value| {
+                    assignment[v] = Some(value);
+                    eval(c, assignment) != Some(false)
+                });
+                match holds {
+                    [false, false] => {
+                        assignment[v] = None;
+                        return false;
+                    }
+                    [true, true] => assignment[v] = None,
+                    [value, _] => {
+                        assignment[v] = Some(!value);
+                        trail.push(v);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return true;
+            }
+        }
+    }
+
+    /// 伝播してから未定の変数を順に割り当て、偽になる制約が出たら戻る。実装しない側を先に試す
+    ///
+    /// 上限に達したら `None`
+    fn search(&mut self, assignment: &mut [Option<bool>]) -> Option<bool> {
+        self.steps += 1;
+        if self.steps > SEARCH_LIMIT {
+            return None;
+        }
+        let mut trail = Vec::new();
+        let undo = |assignment: &mut [Option<bool>], trail: &[usize]| {
+            for v in trail {
+                assignment[*v] = None;
+            }
+        };
+        if !self.propagate(assignment, &mut trail) {
+            undo(assignment, &trail);
+            return Some(false);
+        }
+        let Some(v) = assignment.iter().position(|a| a.is_none()) else {
+            return Some(true);
+        };
+        for value in [false, true] {
+            assignment[v] = Some(value);
+            if self.search(assignment)? {
+                return Some(true);
+            }
+        }
+        assignment[v] = None;
+        undo(assignment, &trail);
+        Some(false)
+    }
+}
+
+/// `vars` 個の変数の割り当てで `constraints` を全て満たすものを探す
+fn solve(constraints: &[Formula<usize>], vars: usize) -> Option<Option<Vec<bool>>> {
+    let mut assignment = vec![None; vars];
+    let found = Search::new(constraints).search(&mut assignment)?;
+    Some(found.then(|| assignment.into_iter().map(|v| v.unwrap_or(false)).collect()))
+}
+
+impl<'a> ConflictCheckEnv<'a> {
+    /// 上位トレイトの論理式。`ct` を実装する型はこれらを満たす
+    fn supertrait_formulas(
+        &self,
+        ty: &ConcreteType,
+        ct: &ConcreteTrait,
+    ) -> Result<Vec<Formula<ConcreteTrait>>> {
+        match &self.checker.traits[ct.id.0].supertraits {
+            Some(b) => {
+                let args = Self::trait_args(ty, ct);
+                b.formulas
+                    .iter()
+                    .map(|f| f.try_map(&mut |t| self.lower_trait(&args, t)))
+                    .collect()
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// A(b) の計算だけでは判定できず、割り当ての探索が必要か
+    pub fn needs_sat(&self, ty: &ConcreteType, b: &ConcreteBound) -> Result<bool> {
+        if !b.formulas.is_empty() {
+            return Ok(true);
+        }
+        for t in self.a(ty, b)? {
+            if !self.supertrait_formulas(ty, &t)?.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// 型 `ty` が境界 `b` を満たすような、トレイトの割り当てを探す
+    ///
+    /// 変数は `b` に現れるトレイトと、そこから上位トレイト・負の上位トレイトを辿って得られるトレイト。
+    /// 上位トレイトの含意と負の上位トレイトによる除外を制約に加える。
+    /// blanket impl の帰結は加えないので、充足可能と判定される側に倒れる
+    pub fn satisfy(&self, ty: &ConcreteType, b: &ConcreteBound) -> Result<Satisfy> {
+        let mut vars: Vec<ConcreteTrait> = Vec::new();
+        let mut index = HashMap::new();
+        let mut var = |t: &ConcreteTrait, vars: &mut Vec<ConcreteTrait>| {
+            *index.entry(t.clone()).or_insert_with(|| {
+                vars.push(t.clone());
+                vars.len() - 1
+            })
+        };
+        let mut constraints = Vec::new();
+        for t in &b.pos {
+            constraints.push(Formula::Atom(var(t, &mut vars)));
+        }
+        for t in &b.neg {
+            constraints.push(Formula::Not(Box::new(Formula::Atom(var(t, &mut vars)))));
+        }
+        for f in &b.formulas {
+            constraints.push(f.map(&mut |t| var(t, &mut vars)));
+        }
+        // 新しく現れた変数の公理を、変数が増えなくなるまで加える
+        let mut i = 0;
+        while i < vars.len() {
+            let t = vars[i].clone();
+            let not_t = Formula::Not(Box::new(Formula::Atom(i)));
+            for s in self.sup(ty, &t)? {
+                let s = Formula::Atom(var(&s, &mut vars));
+                constraints.push(Formula::Or(vec![not_t.clone(), s]));
+            }
+            for n in self.excluded_by(ty, &t)? {
+                let n = Formula::Not(Box::new(Formula::Atom(var(&n, &mut vars))));
+                constraints.push(Formula::Or(vec![not_t.clone(), n]));
+            }
+            for f in self.supertrait_formulas(ty, &t)? {
+                let f = f.map(&mut |t| var(t, &mut vars));
+                constraints.push(Formula::Or(vec![not_t.clone(), f]));
+            }
+            i += 1;
+        }
+        Ok(match solve(&constraints, vars.len()) {
+            Some(Some(assignment)) => Satisfy::Yes(vars.into_iter().zip(assignment).collect()),
+            Some(None) => Satisfy::No,
+            None => Satisfy::Unknown,
+        })
+    }
+
+    /// 割り当てを、`b` に現れるトレイトについての境界として示す
+    pub fn witness(
+        &self,
+        ty: &ConcreteType,
+        b: &ConcreteBound,
+        model: &Model,
+    ) -> Result<ConcreteBound> {
+        let mut mentioned: Vec<_> = b.pos.iter().chain(b.neg.iter()).collect();
+        for f in &b.formulas {
+            f.atoms(&mut mentioned);
+        }
+        let mut witness = ConcreteBound::default();
+        for t in mentioned {
+            if model.get(t) == Some(&true) {
+                witness.pos.insert(t.clone());
+            } else {
+                witness.neg.insert(t.clone());
+            }
+        }
+        self.normalize(ty, &witness)
+    }
+
+    /// 論理式を、原子ごとに `ct` が実装するかを impl から調べて評価する
+    pub fn formula_implements(
+        &mut self,
+        unif: &Unifier,
+        ct: &ConcreteType,
+        f: &Formula<ConcreteTrait>,
+        depth: usize,
+    ) -> Result<Implements> {
+        Ok(match f {
+            Formula::Atom(t) => self.implements(unif, ct, t, depth)?,
+            Formula::Not(g) => self.formula_implements(unif, ct, g, depth)?.not(),
+            Formula::And(fs) => {
+                let mut res = Implements::Yes;
+                for g in fs {
+                    res = res.and(self.formula_implements(unif, ct, g, depth)?);
+                }
+                res
+            }
+            Formula::Or(fs) => {
+                let mut res = Implements::No;
+                for g in fs {
+                    res = res.or(self.formula_implements(unif, ct, g, depth)?);
+                }
+                res
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::check_src;
+    use super::*;
+
+    fn atom(v: usize) -> Formula<usize> {
+        Formula::Atom(v)
+    }
+    fn not(v: usize) -> Formula<usize> {
+        Formula::Not(Box::new(atom(v)))
+    }
+
+    #[test]
+    fn propagation_finds_model() {
+        // 0, 0 -> 1, 1 -> 2
+        let constraints = [
+            atom(0),
+            Formula::Or(vec![not(0), atom(1)]),
+            Formula::Or(vec![not(1), atom(2)]),
+        ];
+        assert_eq!(solve(&constraints, 3), Some(Some(vec![true, true, true])));
+    }
+
+    #[test]
+    fn unsatisfiable_constraints() {
+        let constraints = [
+            Formula::Or(vec![atom(0), atom(1)]),
+            Formula::Or(vec![not(0), atom(1)]),
+            not(1),
+        ];
+        assert_eq!(solve(&constraints, 2), Some(None));
+    }
+
+    #[test]
+    fn search_limit_gives_unknown() {
+        // 10 羽の鳩を 9 個の巣に一羽ずつ入れる
+        let (pigeons, holes) = (10, 9);
+        let var = |p: usize, h: usize| p * holes + h;
+        let mut constraints = Vec::new();
+        for p in 0..pigeons {
+            constraints.push(Formula::Or((0..holes).map(|h| atom(var(p, h))).collect()));
+        }
+        for h in 0..holes {
+            for p in 0..pigeons {
+                for q in (p + 1)..pigeons {
+                    constraints.push(Formula::Or(vec![not(var(p, h)), not(var(q, h))]));
+                }
+            }
+        }
+        assert_eq!(solve(&constraints, pigeons * holes), None);
+    }
+
+    /// 最初の impl の最初の型変数と、その境界
+    fn first_bound(checker: &Checker) -> (ConflictCheckEnv<'_>, ConcreteType, ConcreteBound) {
+        let mut env = ConflictCheckEnv::new(checker);
+        env.get_concrete_impl(&checker.impls[0]).unwrap();
+        let ty = env.param_ty(0);
+        let b = env.params[0].clone().unwrap();
+        (env, ty, b)
+    }
+
+    #[test]
+    fn witness_respects_supertraits() {
+        let src = "trait C {}
+trait A: C {}
+trait B {}
+trait Tr {}
+impl<T: (A | B) - C> Tr for T {}
+";
+        let (checker, _) = check_src(src, Default::default());
+        let (env, ty, b) = first_bound(&checker);
+        let Satisfy::Yes(model) = env.satisfy(&ty, &b).unwrap() else {
+            panic!("{} is not satisfiable", b);
+        };
+        assert_eq!(env.witness(&ty, &b, &model).unwrap().to_string(), "B - C");
+    }
+
+    #[test]
+    fn formula_excluded_by_supertrait_unsatisfiable() {
+        let src = "trait C {}
+trait A: C {}
+trait B: C {}
+trait Tr {}
+impl<T: (A | B) - C> Tr for T {}
+";
+        let (checker, _) = check_src(src, Default::default());
+        let (env, ty, b) = first_bound(&checker);
+        assert!(matches!(env.satisfy(&ty, &b).unwrap(), Satisfy::No));
+    }
+
+    #[test]
+    fn negative_only_bound_display() {
+        let src = "trait C {}
+trait Tr {}
+impl<T: - C> Tr for T {}
+";
+        let (checker, _) = check_src(src, Default::default());
+        assert_eq!(checker.impls[0].to_string(), "impl<T: -C> Tr for T");
+        let (_, _, b) = first_bound(&checker);
+        assert_eq!(b.to_string(), "-C");
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt::Display;
+
+/// 検査中に見つかった問題
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// 上位トレイトの循環 (先頭のトレイトに戻る)
+    SupertraitCycle(Vec<String>),
+    /// 境界を満たす型が存在せず、適用されることのない impl
+    DeadImpl {
+        impl_: String,
+        param: String,
+        pos: String,
+        neg: String,
+        /// `neg` を負の上位トレイトとして除外するトレイト。`None` なら impl の負の境界
+        by: Option<String>,
+    },
+    /// 境界の論理式を満たすトレイトの割り当てが存在せず、適用されることのない impl
+    UnsatisfiableBound {
+        impl_: String,
+        param: String,
+        bound: String,
+    },
+    /// where 節の等式・不等式が両立せず、適用されることのない impl
+    UnsatisfiablePredicates { impl_: String, reason: String },
+    /// トレイトにも実装先の型にも現れない impl の型変数
+    UnconstrainedParam { impl_: String, param: String },
+    /// より一般的な impl に覆われていて、単独では意味を持たない impl
+    ShadowedImpl { impl_: String, by: String },
+    /// 構造体・トレイトの型変数に宣言された境界を満たさない使用を含む impl
+    IllFormed {
+        impl_: String,
+        ty: String,
+        bound: String,
+        by: String,
+    },
+    /// 宣言された境界を満たすか判定できず、証明が必要な条件
+    Obligation {
+        impl_: String,
+        ty: String,
+        bound: String,
+        by: String,
+    },
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::SupertraitCycle(names) => {
+                write!(f, "error: supertrait cycle ")?;
+                for n in names {
+                    write!(f, "{} -> ", n)?;
+                }
+                write!(f, "{}", names.first().map(|n| n.as_str()).unwrap_or(""))
+            }
+            Diagnostic::DeadImpl {
+                impl_,
+                param,
+                pos,
+                neg,
+                by: None,
+            } => write!(
+                f,
+                "warning: {} never applies: {}: {} implies {}, which is excluded",
+                impl_, param, pos, neg
+            ),
+            Diagnostic::DeadImpl {
+                impl_,
+                param,
+                pos,
+                neg,
+                by: Some(by),
+            } => {
+                write!(
+                    f,
+                    "warning: {} never applies: {}: {} excludes {}",
+                    impl_, param, by, neg
+                )?;
+                if pos != neg {
+                    write!(f, ", which {} implies", pos)?;
+                }
+                Ok(())
+            }
+            Diagnostic::UnsatisfiableBound {
+                impl_,
+                param,
+                bound,
+            } => write!(
+                f,
+                "warning: {} never applies: {}: {} is unsatisfiable",
+                impl_, param, bound
+            ),
+            Diagnostic::UnsatisfiablePredicates { impl_, reason } => write!(
+                f,
+                "warning: {} never applies: where clause cannot hold: {}",
+                impl_, reason
+            ),
+            Diagnostic::UnconstrainedParam { impl_, param } => write!(
+                f,
+                "error: {} in {} is not constrained by the trait or self type",
+                param, impl_
+            ),
+            Diagnostic::ShadowedImpl { impl_, by } => {
+                write!(f, "warning: {} is shadowed by {}", impl_, by)
+            }
+            Diagnostic::IllFormed {
+                impl_,
+                ty,
+                bound,
+                by,
+            } => write!(
+                f,
+                "error: {} is ill-formed: {} requires {}: {}, which does not hold",
+                impl_, by, ty, bound
+            ),
+            Diagnostic::Obligation {
+                impl_,
+                ty,
+                bound,
+                by,
+            } => write!(
+                f,
+                "note: {} must prove {}: {} required by {}",
+                impl_, ty, bound, by
+            ),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use super::{write_bound, write_where, Formula};
+use std::fmt::Display;
+
+/// 宣言された構造体の番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StructId(pub usize);
+/// 宣言されたトレイトの番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraitId(pub usize);
+/// 宣言の型変数リスト中の位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParamId(pub usize);
+
+/// 名前解決済みの型式
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Struct {
+        id: StructId,
+        name: String,
+        params: Vec<Ty>,
+    },
+    Param {
+        id: ParamId,
+        name: String,
+    },
+    /// `<self_ty as trait_ref>::name`
+    Projection {
+        self_ty: Box<Ty>,
+        trait_ref: TraitRef,
+        name: String,
+    },
+    /// 解決できなかった型式。名前解決で報告済みで、衝突の検査には渡らない
+    Error(String),
+}
+/// 名前解決済みのトレイトの使用
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraitRef {
+    pub(crate) id: TraitId,
+    pub(crate) name: String,
+    pub(crate) params: Vec<Ty>,
+    /// `Item = u8` のような関連型の指定
+    pub(crate) assoc: Vec<(String, Ty)>,
+}
+#[derive(Debug, Clone)]
+pub struct Bound {
+    pub(crate) pos: Vec<TraitRef>,
+    pub(crate) neg: Vec<TraitRef>,
+    pub(crate) formulas: Vec<Formula<TraitRef>>,
+}
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub(crate) name: String,
+    pub(crate) bound: Option<Bound>,
+    pub(crate) default: Option<Ty>,
+}
+#[derive(Debug, Clone)]
+pub struct Struct {
+    pub(crate) name: String,
+    pub(crate) params: Vec<Param>,
+    pub(crate) predicates: Vec<Predicate>,
+}
+#[derive(Debug, Clone)]
+pub struct Trait {
+    pub(crate) name: String,
+    pub(crate) params: Vec<Param>,
+    pub(crate) supertraits: Option<Bound>,
+    /// `where Self: ..` は `supertraits` に含まれる
+    pub(crate) predicates: Vec<Predicate>,
+}
+/// where 節の述語
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    Eq(Ty, Ty),
+    Ne(Ty, Ty),
+    Bound(Ty, Bound),
+}
+#[derive(Debug, Clone)]
+pub struct Impl {
+    pub(crate) params: Vec<Param>,
+    pub(crate) trait_exp: TraitRef,
+    pub(crate) impl_for: Ty,
+    pub(crate) predicates: Vec<Predicate>,
+    /// `type Item = u8;` のような関連型の定義
+    pub(crate) assoc: Vec<(String, Ty)>,
+    /// 既定値を補う前の impl。報告にはこちらを使う
+    pub(crate) source: String,
+}
+
+/// 名前解決済みのプログラム。`StructId`, `TraitId` はそれぞれ `structs`, `traits` の添字
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub(crate) structs: Vec<Struct>,
+    pub(crate) traits: Vec<Trait>,
+    pub(crate) impls: Vec<Impl>,
+}
+
+impl Ty {
+    /// 型変数 `k` を `arg(k)` で置き換える。置き換えられない型変数があれば `None`
+    pub(crate) fn subst(&self, arg: &dyn Fn(usize) -> Option<Ty>) -> Option<Ty> {
+        match self {
+            Ty::Struct { id, name, params } => Some(Ty::Struct {
+                id: *id,
+                name: name.clone(),
+                params: params.iter().map(|p| p.subst(arg)).collect::<Option<_>>()?,
+            }),
+            Ty::Param { id, .. } => arg(id.0),
+            Ty::Error(name) => Some(Ty::Error(name.clone())),
+            Ty::Projection {
+                self_ty,
+                trait_ref,
+                name,
+            } => Some(Ty::Projection {
+                self_ty: Box::new(self_ty.subst(arg)?),
+                trait_ref: trait_ref.subst(arg)?,
+                name: name.clone(),
+            }),
+        }
+    }
+    /// 型式中の射影を、内側のものから順に集める
+    pub(crate) fn projections<'a>(&'a self, res: &mut Vec<&'a Ty>) {
+        match self {
+            Ty::Struct { params, .. } => params.iter().for_each(|p| p.projections(res)),
+            Ty::Param { .. } | Ty::Error(_) => {}
+            Ty::Projection {
+                self_ty, trait_ref, ..
+            } => {
+                self_ty.projections(res);
+                trait_ref.projections(res);
+                res.push(self);
+            }
+        }
+    }
+}
+impl TraitRef {
+    pub(crate) fn projections<'a>(&'a self, res: &mut Vec<&'a Ty>) {
+        for t in self.params.iter().chain(self.assoc.iter().map(|(_, t)| t)) {
+            t.projections(res);
+        }
+    }
+    pub(crate) fn subst(&self, arg: &dyn Fn(usize) -> Option<Ty>) -> Option<TraitRef> {
+        Some(TraitRef {
+            id: self.id,
+            name: self.name.clone(),
+            params: self
+                .params
+                .iter()
+                .map(|p| p.subst(arg))
+                .collect::<Option<_>>()?,
+            assoc: self
+                .assoc
+                .iter()
+                .map(|(n, t)| Some((n.clone(), t.subst(arg)?)))
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
+impl Bound {
+    pub(crate) fn projections<'a>(&'a self, res: &mut Vec<&'a Ty>) {
+        let mut atoms: Vec<_> = self.pos.iter().chain(self.neg.iter()).collect();
+        for f in &self.formulas {
+            f.atoms(&mut atoms);
+        }
+        for t in atoms {
+            t.projections(res);
+        }
+    }
+}
+
+fn write_list<T: Display>(f: &mut std::fmt::Formatter<'_>, items: &[T]) -> std::fmt::Result {
+    let mut iter = items.iter();
+    if let Some(first) = iter.next() {
+        write!(f, "<{}", first)?;
+        for i in iter {
+            write!(f, ", {}", i)?;
+        }
+        write!(f, ">")?;
+    }
+    Ok(())
+}
+impl Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Struct { name, params, .. } => {
+                write!(f, "{}", name)?;
+                write_list(f, params)
+            }
+            Ty::Param { name, .. } | Ty::Error(name) => write!(f, "{}", name),
+            Ty::Projection {
+                self_ty,
+                trait_ref,
+                name,
+            } => write!(f, "<{} as {}>::{}", self_ty, trait_ref, name),
+        }
+    }
+}
+impl Display for TraitRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        let params = self.params.iter().map(|t| t.to_string());
+        let assoc = self.assoc.iter().map(|(n, t)| format!("{} = {}", n, t));
+        let args: Vec<_> = params.chain(assoc).collect();
+        write_list(f, &args)
+    }
+}
+impl Display for Bound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pos: Vec<_> = self.pos.iter().map(|t| t.to_string()).collect();
+        let neg: Vec<_> = self.neg.iter().map(|t| t.to_string()).collect();
+        write_bound(f, &pos, &self.formulas, &neg)
+    }
+}
+impl Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(b) = &self.bound {
+            write!(f, ": {}", b)?;
+        }
+        if let Some(d) = &self.default {
+            write!(f, " = {}", d)?;
+        }
+        Ok(())
+    }
+}
+impl Display for Struct {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "struct {}", self.name)?;
+        write_list(f, &self.params)?;
+        write_where(f, &self.predicates)
+    }
+}
+impl Display for Trait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "trait {}", self.name)?;
+        write_list(f, &self.params)?;
+        if let Some(b) = &self.supertraits {
+            write!(f, ": {}", b)?;
+        }
+        write_where(f, &self.predicates)
+    }
+}
+impl Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Predicate::Eq(l, r) => write!(f, "{} == {}", l, r),
+            Predicate::Ne(l, r) => write!(f, "{} != {}", l, r),
+            Predicate::Bound(t, b) => write!(f, "{}: {}", t, b),
+        }
+    }
+}
+impl Display for Impl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
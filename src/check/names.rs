@@ -0,0 +1,689 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+/// 名前解決で見つかった問題
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameDiagnostic {
+    /// 同名の構造体・トレイトが複数宣言されている
+    DuplicateDecl(String),
+    /// 同じ型変数リストに同名の型変数がある
+    DuplicateParam { name: String, decl: String },
+    /// パラメータを伴う、宣言されていない構造体
+    UndefinedStruct { name: String, decl: String },
+    /// 構造体としても型変数としても宣言されていない名前
+    UndefinedType { name: String, decl: String },
+    /// 宣言されていないトレイト
+    UndefinedTrait { name: String, decl: String },
+    /// 型の位置にトレイトが使われている
+    NotAType { name: String, decl: String },
+    /// トレイトの位置に構造体・型変数が使われている
+    NotATrait { name: String, decl: String },
+    /// 型変数にパラメータが与えられている
+    ParamApplied { name: String, decl: String },
+    /// 構造体と同名の型変数
+    ShadowedStruct { name: String, decl: String },
+    /// 既定値の無い型変数より前にある、既定値を持つ型変数
+    DefaultNotTrailing { name: String, decl: String },
+    /// トレイトに宣言されていない関連型
+    UndefinedAssoc {
+        name: String,
+        trait_: String,
+        decl: String,
+    },
+    /// impl が定義していない、トレイトの関連型
+    MissingAssoc {
+        name: String,
+        trait_: String,
+        decl: String,
+    },
+    /// 型の位置に関連型の指定がある
+    AssocOnType { name: String, decl: String },
+}
+impl NameDiagnostic {
+    pub fn is_error(&self) -> bool {
+        !matches!(self, NameDiagnostic::ShadowedStruct { .. })
+    }
+}
+
+impl Display for NameDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameDiagnostic::DuplicateDecl(name) => {
+                write!(f, "error: {} declared more than once", name)
+            }
+            NameDiagnostic::DuplicateParam { name, decl } => {
+                write!(
+                    f,
+                    "error: param {} declared more than once in {}",
+                    name, decl
+                )
+            }
+            NameDiagnostic::UndefinedStruct { name, decl } => {
+                write!(f, "error: undefined struct {} in {}", name, decl)
+            }
+            NameDiagnostic::UndefinedType { name, decl } => {
+                write!(
+                    f,
+                    "error: undefined struct or type param {} in {}",
+                    name, decl
+                )
+            }
+            NameDiagnostic::UndefinedTrait { name, decl } => {
+                write!(f, "error: undefined trait {} in {}", name, decl)
+            }
+            NameDiagnostic::NotAType { name, decl } => {
+                write!(f, "error: trait {} used as a type in {}", name, decl)
+            }
+            NameDiagnostic::NotATrait { name, decl } => {
+                write!(
+                    f,
+                    "error: {} used as a trait in {}, but it is a type",
+                    name, decl
+                )
+            }
+            NameDiagnostic::ParamApplied { name, decl } => {
+                write!(
+                    f,
+                    "error: type param {} applied to params in {}",
+                    name, decl
+                )
+            }
+            NameDiagnostic::ShadowedStruct { name, decl } => {
+                write!(
+                    f,
+                    "warning: type param {} shadows struct {} in {}",
+                    name, name, decl
+                )
+            }
+            NameDiagnostic::DefaultNotTrailing { name, decl } => {
+                write!(
+                    f,
+                    "error: param {} with a default must come after params without defaults in {}",
+                    name, decl
+                )
+            }
+            NameDiagnostic::UndefinedAssoc { name, trait_, decl } => {
+                write!(
+                    f,
+                    "error: trait {} has no associated type {} in {}",
+                    trait_, name, decl
+                )
+            }
+            NameDiagnostic::MissingAssoc { name, trait_, decl } => {
+                write!(
+                    f,
+                    "error: {} does not define associated type {} of {}",
+                    decl, name, trait_
+                )
+            }
+            NameDiagnostic::AssocOnType { name, decl } => {
+                write!(
+                    f,
+                    "error: associated type binding on type {} in {}",
+                    name, decl
+                )
+            }
+        }
+    }
+}
+
+/// 宣言の表と、解決中の宣言の型変数
+struct NameEnv<'p> {
+    structs: HashMap<&'p str, ir::StructId>,
+    traits: HashMap<&'p str, ir::TraitId>,
+    /// 既定値を補うための、構造体・トレイトの型変数リスト
+    struct_params: HashMap<&'p str, &'p [Param]>,
+    trait_params: HashMap<&'p str, &'p [Param]>,
+    /// トレイトの関連型の名前
+    trait_assoc: HashMap<&'p str, &'p [String]>,
+    /// 既定値を補っている最中の宣言。既定値が自身を参照しても止まるようにする
+    filling: Vec<String>,
+    diagnostics: Vec<NameDiagnostic>,
+}
+impl<'p> NameEnv<'p> {
+    fn new(Program(decls): &'p Program) -> Self {
+        let mut env = NameEnv {
+            structs: HashMap::new(),
+            traits: HashMap::new(),
+            struct_params: HashMap::new(),
+            trait_params: HashMap::new(),
+            trait_assoc: HashMap::new(),
+            filling: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        let mut declared = HashSet::new();
+        for d in decls {
+            let name = match d {
+                Decl::Struct(s) => {
+                    let id = ir::StructId(env.structs.len());
+                    env.structs.entry(&s.name).or_insert(id);
+                    env.struct_params
+                        .entry(&s.name)
+                        .or_insert(s.params.as_deref().unwrap_or(&[]));
+                    &s.name
+                }
+                Decl::Trait(t) => {
+                    let id = ir::TraitId(env.traits.len());
+                    env.traits.entry(&t.name).or_insert(id);
+                    env.trait_params.entry(&t.name).or_insert(&t.params);
+                    env.trait_assoc.entry(&t.name).or_insert(&t.assoc_types);
+                    &t.name
+                }
+                Decl::Impl(_) => continue,
+            };
+            if !declared.insert(name.as_str()) {
+                env.diagnostics
+                    .push(NameDiagnostic::DuplicateDecl(name.clone()));
+            }
+        }
+        env
+    }
+
+    /// トレイト `tr` が関連型 `name` を宣言しているか。無ければ報告する
+    fn check_assoc(&mut self, tr: &ir::TraitRef, name: &str, decl: &str) -> bool {
+        if self.trait_assoc[tr.name.as_str()].iter().any(|a| a == name) {
+            return true;
+        }
+        self.diagnostics.push(NameDiagnostic::UndefinedAssoc {
+            name: name.to_string(),
+            trait_: tr.name.clone(),
+            decl: decl.to_string(),
+        });
+        false
+    }
+    /// 型式を解決する。解決できない部分は報告して `Ty::Error` にする
+    fn lower_type(&mut self, scope: &[String], te: &TExp, decl: &str) -> ir::Ty {
+        if let Some(q) = &te.qself {
+            let self_ty = self.lower_type(scope, &q.0, decl);
+            let trait_ref = self.lower_trait(scope, Some(&self_ty), &q.1, decl);
+            return match trait_ref {
+                Some(trait_ref) if self.check_assoc(&trait_ref, &te.name, decl) => {
+                    ir::Ty::Projection {
+                        self_ty: Box::new(self_ty),
+                        trait_ref,
+                        name: te.name.clone(),
+                    }
+                }
+                _ => ir::Ty::Error(te.to_string()),
+            };
+        }
+        let mut params: Vec<_> = te
+            .params
+            .iter()
+            .map(|p| self.lower_type(scope, p, decl))
+            .collect();
+        let name = te.name.clone();
+        let decl = decl.to_string();
+        if !te.assoc.is_empty() {
+            self.diagnostics
+                .push(NameDiagnostic::AssocOnType { name, decl });
+            return ir::Ty::Error(te.to_string());
+        }
+        // 型変数は構造体より優先される
+        if let Some(k) = scope.iter().position(|p| *p == te.name) {
+            if !te.params.is_empty() {
+                self.diagnostics
+                    .push(NameDiagnostic::ParamApplied { name, decl });
+                return ir::Ty::Error(te.to_string());
+            }
+            return ir::Ty::Param {
+                id: ir::ParamId(k),
+                name,
+            };
+        }
+        if let Some(id) = self.structs.get(te.name.as_str()).copied() {
+            let decl_params = self.struct_params[te.name.as_str()];
+            self.fill_defaults(&te.name, decl_params, false, &mut params, None);
+            return ir::Ty::Struct { id, name, params };
+        }
+        let d = if self.traits.contains_key(te.name.as_str()) {
+            NameDiagnostic::NotAType { name, decl }
+        } else if te.params.is_empty() && !scope.is_empty() {
+            NameDiagnostic::UndefinedType { name, decl }
+        } else {
+            NameDiagnostic::UndefinedStruct { name, decl }
+        };
+        self.diagnostics.push(d);
+        ir::Ty::Error(te.to_string())
+    }
+    /// 省略された末尾の型引数を既定値で補う。`self_ty` はトレイトの `Self` に当たる型
+    ///
+    /// 補えない引数があればそこで止め、数の誤りは wf で報告する
+    fn fill_defaults(
+        &mut self,
+        name: &str,
+        decl_params: &[Param],
+        is_trait: bool,
+        args: &mut Vec<ir::Ty>,
+        self_ty: Option<&ir::Ty>,
+    ) {
+        if args.len() >= decl_params.len() || self.filling.iter().any(|n| n == name) {
+            return;
+        }
+        self.filling.push(name.to_string());
+        let mut scope: Vec<_> = decl_params.iter().map(|p| p.name.clone()).collect();
+        if is_trait {
+            scope.push("Self".to_string());
+        }
+        // 既定値自体の誤りは宣言の解決で報告される
+        let diagnostics = std::mem::take(&mut self.diagnostics);
+        for p in &decl_params[args.len()..] {
+            let Some(d) = &p.default else {
+                break;
+            };
+            let d = self.lower_type(&scope, d, "");
+            let arg = |k: usize| {
+                if k < args.len() {
+                    Some(args[k].clone())
+                } else if is_trait && k == decl_params.len() {
+                    self_ty.cloned()
+                } else {
+                    None
+                }
+            };
+            match d.subst(&arg) {
+                Some(t) => args.push(t),
+                None => break,
+            }
+        }
+        self.diagnostics = diagnostics;
+        self.filling.pop();
+    }
+    /// トレイトの使用を解決する。トレイトが解決できなければ報告して `None`
+    fn lower_trait(
+        &mut self,
+        scope: &[String],
+        self_ty: Option<&ir::Ty>,
+        te: &TExp,
+        decl: &str,
+    ) -> Option<ir::TraitRef> {
+        let mut params: Vec<_> = te
+            .params
+            .iter()
+            .map(|p| self.lower_type(scope, p, decl))
+            .collect();
+        let assoc: Vec<_> = te
+            .assoc
+            .iter()
+            .map(|(n, t)| (n, self.lower_type(scope, t, decl)))
+            .collect();
+        let name = te.name.clone();
+        let decl = decl.to_string();
+        let Some(id) = self.traits.get(te.name.as_str()) else {
+            let d = if scope.contains(&te.name) || self.structs.contains_key(te.name.as_str()) {
+                NameDiagnostic::NotATrait { name, decl }
+            } else {
+                NameDiagnostic::UndefinedTrait { name, decl }
+            };
+            self.diagnostics.push(d);
+            return None;
+        };
+        let id = *id;
+        let decl_params = self.trait_params[te.name.as_str()];
+        self.fill_defaults(&te.name, decl_params, true, &mut params, self_ty);
+        let mut tr = ir::TraitRef {
+            id,
+            name,
+            params,
+            assoc: Vec::new(),
+        };
+        for (n, t) in assoc {
+            if self.check_assoc(&tr, n, &decl) {
+                tr.assoc.push((n.clone(), t));
+            }
+        }
+        Some(tr)
+    }
+    /// `self_ty` は境界を課される型。解決できないトレイトを含む原子・論理式は除く
+    fn lower_bound(
+        &mut self,
+        scope: &[String],
+        self_ty: Option<&ir::Ty>,
+        b: &Bound,
+        decl: &str,
+    ) -> ir::Bound {
+        let pos = b
+            .pos
+            .iter()
+            .filter_map(|t| self.lower_trait(scope, self_ty, t, decl))
+            .collect();
+        let neg = b
+            .neg
+            .iter()
+            .filter_map(|t| self.lower_trait(scope, self_ty, t, decl))
+            .collect();
+        // 誤りを全て報告するため、先に全ての原子を解決する
+        let formulas: Vec<_> = b
+            .formulas
+            .iter()
+            .map(|f| f.map(&mut |t| self.lower_trait(scope, self_ty, t, decl)))
+            .collect();
+        let formulas = formulas
+            .iter()
+            .filter_map(|f| f.try_map(&mut |t| t.clone().ok_or(())).ok())
+            .collect();
+        ir::Bound { pos, neg, formulas }
+    }
+    fn lower_predicate(&mut self, scope: &[String], p: &Predicate, decl: &str) -> ir::Predicate {
+        match p {
+            Predicate::Eq(l, r) => ir::Predicate::Eq(
+                self.lower_type(scope, l, decl),
+                self.lower_type(scope, r, decl),
+            ),
+            Predicate::Ne(l, r) => ir::Predicate::Ne(
+                self.lower_type(scope, l, decl),
+                self.lower_type(scope, r, decl),
+            ),
+            Predicate::Bound(t, b) => {
+                let t = self.lower_type(scope, t, decl);
+                let b = self.lower_bound(scope, Some(&t), b, decl);
+                ir::Predicate::Bound(t, b)
+            }
+        }
+    }
+    fn lower_predicates(
+        &mut self,
+        scope: &[String],
+        predicates: &[Predicate],
+        decl: &str,
+    ) -> Vec<ir::Predicate> {
+        predicates
+            .iter()
+            .map(|p| self.lower_predicate(scope, p, decl))
+            .collect()
+    }
+    /// 型変数リストを解決する。境界には `scope` の全ての型変数が現れてよい
+    fn lower_params(&mut self, params: &[Param], scope: &[String], decl: &str) -> Vec<ir::Param> {
+        let mut seen = HashSet::new();
+        let mut defaulted = None;
+        for p in params {
+            match (&p.default, defaulted) {
+                (Some(_), None) => defaulted = Some(p),
+                (None, Some(d)) => {
+                    self.diagnostics.push(NameDiagnostic::DefaultNotTrailing {
+                        name: d.name.clone(),
+                        decl: decl.to_string(),
+                    });
+                    defaulted = None;
+                }
+                _ => {}
+            }
+            if !seen.insert(p.name.as_str()) {
+                self.diagnostics.push(NameDiagnostic::DuplicateParam {
+                    name: p.name.clone(),
+                    decl: decl.to_string(),
+                });
+            }
+            if self.structs.contains_key(p.name.as_str()) {
+                self.diagnostics.push(NameDiagnostic::ShadowedStruct {
+                    name: p.name.clone(),
+                    decl: decl.to_string(),
+                });
+            }
+        }
+        params
+            .iter()
+            .enumerate()
+            .map(|(k, p)| {
+                let self_ty = ir::Ty::Param {
+                    id: ir::ParamId(k),
+                    name: p.name.clone(),
+                };
+                ir::Param {
+                    name: p.name.clone(),
+                    bound: p
+                        .bound
+                        .as_ref()
+                        .map(|b| self.lower_bound(scope, Some(&self_ty), b, decl)),
+                    default: p.default.as_ref().map(|d| self.lower_type(scope, d, decl)),
+                }
+            })
+            .collect()
+    }
+    /// impl の関連型の定義を解決する。トレイトの関連型は全て定義されていなければならない
+    fn lower_assoc_defs(
+        &mut self,
+        scope: &[String],
+        tr: &ir::TraitRef,
+        defs: &[(String, TExp)],
+        decl: &str,
+    ) -> Vec<(String, ir::Ty)> {
+        let mut lowered = Vec::new();
+        for (name, t) in defs {
+            let declared = self.check_assoc(tr, name, decl);
+            let t = self.lower_type(scope, t, decl);
+            if declared {
+                lowered.push((name.clone(), t));
+            }
+        }
+        for name in self.trait_assoc[tr.name.as_str()] {
+            if !defs.iter().any(|(n, _)| n == name) {
+                self.diagnostics.push(NameDiagnostic::MissingAssoc {
+                    name: name.clone(),
+                    trait_: tr.name.clone(),
+                    decl: decl.to_string(),
+                });
+            }
+        }
+        lowered
+    }
+    fn lower_decl(&mut self, d: &Decl, program: &mut ir::Program) {
+        match d {
+            Decl::Struct(s) => {
+                let decl = format!("struct {}", s);
+                let params = s.params.as_deref().unwrap_or(&[]);
+                let scope: Vec<_> = params.iter().map(|p| p.name.clone()).collect();
+                let params = self.lower_params(params, &scope, &decl);
+                let predicates = self.lower_predicates(&scope, &s.predicates, &decl);
+                program.structs.push(ir::Struct {
+                    name: s.name.clone(),
+                    params,
+                    predicates,
+                });
+            }
+            Decl::Trait(t) => {
+                let decl = t.to_string();
+                // `Self` は最後の型変数として扱い、実装する型に置き換えられる
+                let mut scope: Vec<_> = t.params.iter().map(|p| p.name.clone()).collect();
+                scope.push("Self".to_string());
+                let params = self.lower_params(&t.params, &scope, &decl);
+                // `where Self: B` は上位トレイトとして扱う
+                let mut supertraits = t.supertraits.clone();
+                let mut predicates = Vec::new();
+                for p in &t.predicates {
+                    match p {
+                        Predicate::Bound(t, b)
+                            if t.name == "Self"
+                                && t.params.is_empty()
+                                && t.assoc.is_empty()
+                                && t.qself.is_none() =>
+                        {
+                            let s = supertraits.get_or_insert_with(Bound::default);
+                            s.pos.extend(b.pos.iter().cloned());
+                            s.neg.extend(b.neg.iter().cloned());
+                            s.formulas.extend(b.formulas.iter().cloned());
+                        }
+                        _ => predicates.push(p.clone()),
+                    }
+                }
+                let self_ty = ir::Ty::Param {
+                    id: ir::ParamId(t.params.len()),
+                    name: "Self".to_string(),
+                };
+                let supertraits = supertraits
+                    .as_ref()
+                    .map(|b| self.lower_bound(&scope, Some(&self_ty), b, &decl));
+                let predicates = self.lower_predicates(&scope, &predicates, &decl);
+                program.traits.push(ir::Trait {
+                    name: t.name.clone(),
+                    params,
+                    supertraits,
+                    predicates,
+                });
+            }
+            Decl::Impl(i) => {
+                let decl = i.to_string();
+                let scope: Vec<_> = i.params.iter().map(|p| p.name.clone()).collect();
+                let params = self.lower_params(&i.params, &scope, &decl);
+                let impl_for = self.lower_type(&scope, &i.impl_for, &decl);
+                let trait_exp = self.lower_trait(&scope, Some(&impl_for), &i.trait_exp, &decl);
+                let predicates = self.lower_predicates(&scope, &i.predicates, &decl);
+                // トレイトが解決できなければ impl は組み立てられない
+                let Some(trait_exp) = trait_exp else {
+                    return;
+                };
+                let assoc = self.lower_assoc_defs(&scope, &trait_exp, &i.assoc, &decl);
+                program.impls.push(ir::Impl {
+                    source: decl,
+                    params,
+                    trait_exp,
+                    impl_for,
+                    predicates,
+                    assoc,
+                });
+            }
+        }
+    }
+}
+
+/// 名前を解決して IR に変換する。誤りと警告は全て返す
+///
+/// 誤りがあっても、解決できた部分から IR を組み立てて wf の検査に使えるようにする。
+/// その IR は衝突の検査には渡さない
+pub fn lower(p: &Program) -> (ir::Program, Vec<NameDiagnostic>) {
+    let mut env = NameEnv::new(p);
+    let mut program = ir::Program::default();
+    let mut declared = (HashSet::new(), HashSet::new());
+    let Program(decls) = p;
+    for d in decls {
+        // 重複した宣言は最初のものだけを使う
+        let first = match d {
+            Decl::Struct(s) => declared.0.insert(&s.name),
+            Decl::Trait(t) => declared.1.insert(&t.name),
+            Decl::Impl(_) => true,
+        };
+        if first {
+            env.lower_decl(d, &mut program);
+        }
+    }
+    (program, env.diagnostics)
+}
+
+/// `--reference` などで与えられた型変数リストを、`p` の宣言の下で解決する
+pub fn lower_params(p: &Program, params: &[Param]) -> Result<Vec<ir::Param>, Vec<NameDiagnostic>> {
+    let mut env = NameEnv::new(p);
+    let decl = params
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let scope: Vec<_> = params.iter().map(|p| p.name.clone()).collect();
+    let params = env.lower_params(params, &scope, &decl);
+    if env.diagnostics.iter().any(|d| d.is_error()) {
+        Err(env.diagnostics)
+    } else {
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser;
+    use super::*;
+
+    fn diagnostics(src: &str) -> Vec<String> {
+        let program = parser::program(src).expect("Parse error").1;
+        lower(&program).1.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn kinds_of_unknown_names_told_apart() {
+        let src = "struct u8;
+trait Tr {}
+impl<u8> Tr for u8 {}
+impl<T> Tr for Tr {}
+impl<T> T for Vec<T> {}
+impl<T> Tr for T<u8> {}
+impl<T> Tr for X {}
+";
+        assert_eq!(
+            diagnostics(src),
+            vec![
+                "warning: type param u8 shadows struct u8 in impl<u8> Tr for u8",
+                "error: trait Tr used as a type in impl<T> Tr for Tr",
+                "error: undefined struct Vec in impl<T> T for Vec<T>",
+                "error: T used as a trait in impl<T> T for Vec<T>, but it is a type",
+                "error: type param T applied to params in impl<T> Tr for T<u8>",
+                "error: undefined struct or type param X in impl<T> Tr for X",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_resolved_to_ids() {
+        let src = "struct u8;
+struct Vec<T>;
+trait A {}
+trait Tr<T> {}
+impl<u8, T: A> Tr<u8> for Vec<T> {}
+";
+        let program = parser::program(src).unwrap().1;
+        let (lowered, diagnostics) = lower(&program);
+        assert_eq!(diagnostics.len(), 1);
+        let im = &lowered.impls[0];
+        assert_eq!(im.trait_exp.id, ir::TraitId(1));
+        assert_eq!(
+            im.trait_exp.params,
+            vec![ir::Ty::Param {
+                id: ir::ParamId(0),
+                name: "u8".to_string()
+            }]
+        );
+        let ir::Ty::Struct { id, params, .. } = &im.impl_for else {
+            panic!("{} is not a struct", im.impl_for);
+        };
+        assert_eq!(*id, ir::StructId(1));
+        assert_eq!(
+            params[0],
+            ir::Ty::Param {
+                id: ir::ParamId(1),
+                name: "T".to_string()
+            }
+        );
+        assert_eq!(
+            im.params[1].bound.as_ref().unwrap().pos[0].id,
+            ir::TraitId(0)
+        );
+    }
+
+    #[test]
+    fn default_before_non_default_rejected() {
+        let src = "struct H<K, V = K, W>;
+trait Tr<A = H, B> {}
+struct M<K, V = K, S = K>;
+";
+        assert_eq!(
+            diagnostics(src),
+            vec![
+                "error: param V with a default must come after params without defaults in struct H<K, V = K, W>",
+                "error: param A with a default must come after params without defaults in trait Tr<A = H, B>",
+            ]
+        );
+    }
+
+    #[test]
+    fn assoc_definitions_checked() {
+        let src = "struct V;
+trait Iterator { type Item; }
+impl Iterator for V {}
+impl Iterator for V { type Item = V; type Len = V; }
+";
+        assert_eq!(
+            diagnostics(src),
+            vec![
+                "error: impl Iterator for V does not define associated type Item of Iterator",
+                "error: trait Iterator has no associated type Len in impl Iterator for V",
+            ]
+        );
+    }
+}
@@ -14,6 +14,41 @@ pub fn id(s: &str) -> IResult<&str, String> {
 }
 
 pub fn t_exp(s: &str) -> IResult<&str, TExp> {
+    alt((projection, path))(s)
+}
+/// `<T as Tr>::Name` の形の射影
+fn projection(s: &str) -> IResult<&str, TExp> {
+    map(
+        tuple((
+            tag("<"),
+            multispace0,
+            t_exp,
+            multispace1,
+            tag("as"),
+            multispace1,
+            t_exp,
+            multispace0,
+            tag(">::"),
+            id,
+        )),
+        |(_, _, ty, _, _, _, tr, _, _, name)| TExp {
+            name,
+            qself: Some(Box::new((ty, tr))),
+            ..Default::default()
+        },
+    )(s)
+}
+/// 型引数か、`Item = u8` の形の関連型の指定
+fn t_arg(s: &str) -> IResult<&str, (Option<String>, TExp)> {
+    alt((
+        map(
+            tuple((id, multispace0, tag("="), multispace0, t_exp)),
+            |(name, _, _, _, t)| (Some(name), t),
+        ),
+        map(t_exp, |t| (None, t)),
+    ))(s)
+}
+fn path(s: &str) -> IResult<&str, TExp> {
     map(
         tuple((
             id,
@@ -23,30 +58,42 @@ pub fn t_exp(s: &str) -> IResult<&str, TExp> {
                     tag("<"),
                     multispace0,
                     many0(map(
-                        tuple((t_exp, multispace0, tag(","), multispace0)),
-                        |(p_exp, _, _, _)| p_exp,
+                        tuple((t_arg, multispace0, tag(","), multispace0)),
+                        |(arg, _, _, _)| arg,
                     )),
                     multispace0,
-                    t_exp,
+                    t_arg,
                     multispace0,
                     tag(">"),
                 )),
                 |(_, _, _, init, _, last, _, _)| {
-                    let mut params = init;
-                    params.push(last);
-                    params
+                    let mut args = init;
+                    args.push(last);
+                    args
                 },
             )),
         )),
-        |(name, opt_params)| TExp {
-            name,
-            params: opt_params.unwrap_or(Vec::new()),
+        |(name, opt_args)| {
+            let mut params = Vec::new();
+            let mut assoc = Vec::new();
+            for (n, t) in opt_args.unwrap_or_default() {
+                match n {
+                    Some(n) => assoc.push((n, t)),
+                    None => params.push(t),
+                }
+            }
+            TExp {
+                name,
+                params,
+                assoc,
+                qself: None,
+            }
         },
     )(s)
 }
 pub fn param(
     bounds: impl Fn(&str) -> IResult<&str, Bound>,
-) -> impl FnMut(&str) -> IResult<&str, Param> {
+) -> impl Fn(&str) -> IResult<&str, Param> {
     move |s| {
         map(
             tuple((
@@ -56,12 +103,33 @@ pub fn param(
                     |(_, _, _, b)| b,
                 )),
             )),
-            |(name, bound)| Param { name, bound },
+            |(name, bound)| Param {
+                name,
+                bound,
+                default: None,
+            },
         )(s)
     }
 }
-pub fn params(
+/// 構造体・トレイトの型変数。`S = RandomState` のように既定値を書ける
+pub fn decl_param(
     bounds: impl Fn(&str) -> IResult<&str, Bound>,
+) -> impl Fn(&str) -> IResult<&str, Param> {
+    move |s| {
+        map(
+            tuple((
+                param(&bounds),
+                opt(map(
+                    tuple((multispace0, tag("="), multispace0, t_exp)),
+                    |(_, _, _, t)| t,
+                )),
+            )),
+            |(p, default)| Param { default, ..p },
+        )(s)
+    }
+}
+pub fn params(
+    param: impl Fn(&str) -> IResult<&str, Param>,
 ) -> impl FnMut(&str) -> IResult<&str, Vec<Param>> {
     move |s| {
         alt((
@@ -71,11 +139,11 @@ pub fn params(
                     tag("<"),
                     multispace0,
                     many0(map(
-                        tuple((param(&bounds), multispace0, tag(","), multispace0)),
+                        tuple((&param, multispace0, tag(","), multispace0)),
                         |(p, _, _, _)| p,
                     )),
                     multispace0,
-                    param(&bounds),
+                    &param,
                     multispace0,
                     tag(">"),
                 )),
@@ -95,20 +163,35 @@ pub fn trait_def(s: &str) -> IResult<&str, Trait> {
             multispace1,
             id,
             multispace0,
-            opt(params(trait_bound)),
+            opt(params(decl_param(trait_bound))),
             opt(map(
-                tuple((multispace0, tag(":"), multispace0, trait_bound)),
+                tuple((multispace0, tag(":"), multispace0, extend_trait_bound)),
                 |(_, _, _, b)| b,
             )),
             multispace0,
+            opt(where_clause(bound_predicate)),
+            multispace0,
             tag("{"),
             multispace0,
+            many0(map(
+                tuple((
+                    tag("type"),
+                    multispace1,
+                    id,
+                    multispace0,
+                    tag(";"),
+                    multispace0,
+                )),
+                |(_, _, name, _, _, _)| name,
+            )),
             tag("}"),
         )),
-        |(_, _, name, _, opt_params, supertraits, _, _, _, _)| Trait {
+        |(_, _, name, _, opt_params, supertraits, _, predicates, _, _, _, assoc_types, _)| Trait {
             name,
             params: opt_params.unwrap_or(Vec::new()),
             supertraits,
+            predicates: predicates.unwrap_or(Vec::new()),
+            assoc_types,
         },
     )(s)
 }
@@ -118,7 +201,7 @@ pub fn impl_def(s: &str) -> IResult<&str, Impl> {
         tuple((
             tag("impl"),
             multispace0,
-            opt(params(extend_trait_bound)),
+            opt(params(param(extend_trait_bound))),
             multispace0,
             t_exp,
             multispace0,
@@ -126,18 +209,97 @@ pub fn impl_def(s: &str) -> IResult<&str, Impl> {
             multispace1,
             t_exp,
             multispace0,
+            opt(where_clause(predicate)),
+            multispace0,
             tag("{"),
             multispace0,
+            many0(map(
+                tuple((
+                    tag("type"),
+                    multispace1,
+                    id,
+                    multispace0,
+                    tag("="),
+                    multispace0,
+                    t_exp,
+                    multispace0,
+                    tag(";"),
+                    multispace0,
+                )),
+                |(_, _, name, _, _, _, t, _, _, _)| (name, t),
+            )),
             tag("}"),
         )),
-        |(_, _, opt_params, _, trait_exp, _, _, _, impl_for, _, _, _, _)| Impl {
-            params: opt_params.unwrap_or(Vec::new()),
-            trait_exp,
-            impl_for,
+        |(_, _, opt_params, _, trait_exp, _, _, _, impl_for, _, predicates, _, _, _, assoc, _)| {
+            Impl {
+                params: opt_params.unwrap_or(Vec::new()),
+                trait_exp,
+                impl_for,
+                predicates: predicates.unwrap_or(Vec::new()),
+                assoc,
+            }
         },
     )(s)
 }
 
+/// impl の where 節の述語。型の等式・不等式も書ける
+pub fn predicate(s: &str) -> IResult<&str, Predicate> {
+    alt((
+        map(
+            tuple((
+                t_exp,
+                multispace0,
+                alt((tag("=="), tag("!="))),
+                multispace0,
+                t_exp,
+            )),
+            |(l, _, op, _, r)| {
+                if op == "==" {
+                    Predicate::Eq(l, r)
+                } else {
+                    Predicate::Ne(l, r)
+                }
+            },
+        ),
+        bound_predicate,
+    ))(s)
+}
+/// `T: A - B` の形の述語
+pub fn bound_predicate(s: &str) -> IResult<&str, Predicate> {
+    map(
+        tuple((
+            t_exp,
+            multispace0,
+            tag(":"),
+            multispace0,
+            extend_trait_bound,
+        )),
+        |(t, _, _, _, b)| Predicate::Bound(t, b),
+    )(s)
+}
+pub fn where_clause(
+    predicate: impl Fn(&str) -> IResult<&str, Predicate>,
+) -> impl FnMut(&str) -> IResult<&str, Vec<Predicate>> {
+    move |s| {
+        map(
+            tuple((
+                tag("where"),
+                multispace1,
+                &predicate,
+                many0(map(
+                    tuple((multispace0, tag(","), multispace0, &predicate)),
+                    |(_, _, _, p)| p,
+                )),
+            )),
+            |(_, _, first, rest)| {
+                let mut predicates = vec![first];
+                predicates.extend(rest);
+                predicates
+            },
+        )(s)
+    }
+}
+
 pub fn struct_def(s: &str) -> IResult<&str, Struct> {
     map(
         tuple((
@@ -145,11 +307,17 @@ pub fn struct_def(s: &str) -> IResult<&str, Struct> {
             multispace1,
             id,
             multispace0,
-            opt(params(trait_bound)),
+            opt(params(decl_param(extend_trait_bound))),
+            multispace0,
+            opt(where_clause(bound_predicate)),
             multispace0,
             tag(";"),
         )),
-        |(_, _, name, _, params, _, _)| Struct { name, params },
+        |(_, _, name, _, params, _, predicates, _, _)| Struct {
+            name,
+            params,
+            predicates: predicates.unwrap_or(Vec::new()),
+        },
     )(s)
 }
 
@@ -159,16 +327,16 @@ pub fn program(s: &str) -> IResult<&str, Program> {
             tuple((
                 multispace0,
                 alt((
-                    map(struct_def, |d| Decl::Struct(d)),
-                    map(trait_def, |d| Decl::Trait(d)),
-                    map(impl_def, |d| Decl::Impl(d)),
+                    map(struct_def, Decl::Struct),
+                    map(trait_def, Decl::Trait),
+                    map(impl_def, Decl::Impl),
                 )),
                 newline,
                 multispace0,
             )),
             |(_, d, _, _)| d,
         )),
-        |ds| Program(ds),
+        Program,
     ))(s)
 }
 
@@ -186,33 +354,91 @@ pub fn trait_bound(s: &str) -> IResult<&str, Bound> {
             pos.extend(comp);
             Bound {
                 pos,
-                neg: Vec::new(),
+                ..Default::default()
             }
         },
     )(s)
 }
 // Extend
-pub fn extend_trait_bound(s: &str) -> IResult<&str, Bound> {
+/// `+`, `-` で繋いだ項の並び。先頭の項も `-` で否定できる
+fn signed_terms(s: &str) -> IResult<&str, Vec<(bool, Formula<TExp>)>> {
     map(
         tuple((
-            t_exp,
+            opt(tuple((tag("-"), multispace0))),
+            bound_term,
             many0(map(
-                tuple((multispace0, alt((tag("+"), tag("-"))), multispace0, t_exp)),
-                |(_, opr, _, t_exp)| (opr, t_exp),
+                tuple((
+                    multispace0,
+                    alt((tag("+"), tag("-"))),
+                    multispace0,
+                    bound_term,
+                )),
+                |(_, opr, _, f)| (opr == "+", f),
             )),
         )),
-        |(first, tail)| {
-            let mut pos = vec![first];
-            let mut neg = Vec::new();
-
-            for (opr, t_exp) in tail {
-                if opr == "-" {
-                    neg.push(t_exp);
-                } else {
-                    pos.push(t_exp);
-                }
-            }
-            Bound { pos, neg }
+        |(first_neg, first, tail)| {
+            let mut terms = vec![(first_neg.is_none(), first)];
+            terms.extend(tail);
+            terms
         },
     )(s)
 }
+/// トレイトか、括弧で囲んだ `|` で区切った論理式
+fn bound_term(s: &str) -> IResult<&str, Formula<TExp>> {
+    alt((
+        map(
+            tuple((
+                tag("("),
+                multispace0,
+                signed_terms,
+                many0(map(
+                    tuple((multispace0, tag("|"), multispace0, signed_terms)),
+                    |(_, _, _, terms)| terms,
+                )),
+                multispace0,
+                tag(")"),
+            )),
+            |(_, _, first, rest, _, _)| {
+                let conj = |terms: Vec<(bool, Formula<TExp>)>| {
+                    let mut b = Bound::default();
+                    for (positive, f) in terms {
+                        b.push(positive, f);
+                    }
+                    b.into_formula()
+                };
+                if rest.is_empty() {
+                    conj(first)
+                } else {
+                    let mut fs = vec![conj(first)];
+                    fs.extend(rest.into_iter().map(conj));
+                    Formula::Or(fs)
+                }
+            },
+        ),
+        map(t_exp, Formula::Atom),
+    ))(s)
+}
+pub fn extend_trait_bound(s: &str) -> IResult<&str, Bound> {
+    map(signed_terms, |terms| {
+        let mut b = Bound::default();
+        for (positive, f) in terms {
+            b.push(positive, f);
+        }
+        b
+    })(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impl_assoc_definitions_round_trip() {
+        let src = "trait Iterator { type Item; }
+impl<X> Iterator for Vec<X> { type Item = X; }
+impl Iterator for V {}
+";
+        let program = program(src).unwrap().1;
+        assert_eq!(program.to_string(), src);
+    }
+}
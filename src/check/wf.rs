@@ -0,0 +1,241 @@
+use super::ir::*;
+use std::fmt::Display;
+
+/// プログラムの整形式性の誤り
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WfError {
+    /// 宣言とパラメータ数が異なる
+    Arity {
+        name: String,
+        expected: usize,
+        found: usize,
+        decl: String,
+    },
+    /// impl の正の境界以外にある関連型の指定
+    MisplacedAssoc { trait_: String, decl: String },
+    /// impl 以外にある射影
+    MisplacedProjection { ty: String, decl: String },
+}
+
+impl Display for WfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WfError::Arity {
+                name,
+                expected,
+                found,
+                decl,
+            } => write!(
+                f,
+                "error: {} takes {} params but {} given in {}",
+                name, expected, found, decl
+            ),
+            WfError::MisplacedAssoc { trait_, decl } => write!(
+                f,
+                "error: associated type binding in {} is only allowed in positive bounds of impls, found in {}",
+                trait_, decl
+            ),
+            WfError::MisplacedProjection { ty, decl } => write!(
+                f,
+                "error: projection {} is only allowed in impls, found in {}",
+                ty, decl
+            ),
+        }
+    }
+}
+
+/// 宣言を一つずつ検査する環境
+struct WfEnv<'p> {
+    program: &'p Program,
+    errors: Vec<WfError>,
+    /// impl を検査中か。関連型の指定と射影は impl にだけ書ける
+    in_impl: bool,
+}
+impl<'p> WfEnv<'p> {
+    fn check_type(&mut self, ty: &Ty, decl: &str) {
+        match ty {
+            Ty::Struct { id, name, params } => {
+                let expected = self.program.structs[id.0].params.len();
+                self.check_arity(name, expected, params.len(), decl);
+                for p in params {
+                    self.check_type(p, decl);
+                }
+            }
+            Ty::Param { .. } | Ty::Error(_) => {}
+            Ty::Projection {
+                self_ty, trait_ref, ..
+            } => {
+                if !self.in_impl {
+                    self.errors.push(WfError::MisplacedProjection {
+                        ty: ty.to_string(),
+                        decl: decl.to_string(),
+                    });
+                }
+                self.check_type(self_ty, decl);
+                self.check_trait(trait_ref, decl);
+                self.check_no_assoc(trait_ref, decl);
+            }
+        }
+    }
+    fn check_no_assoc(&mut self, tr: &TraitRef, decl: &str) {
+        if !tr.assoc.is_empty() {
+            self.errors.push(WfError::MisplacedAssoc {
+                trait_: tr.to_string(),
+                decl: decl.to_string(),
+            });
+        }
+    }
+    fn check_trait(&mut self, tr: &TraitRef, decl: &str) {
+        let expected = self.program.traits[tr.id.0].params.len();
+        self.check_arity(&tr.name, expected, tr.params.len(), decl);
+        for p in tr.params.iter().chain(tr.assoc.iter().map(|(_, t)| t)) {
+            self.check_type(p, decl);
+        }
+    }
+    fn check_arity(&mut self, name: &str, expected: usize, found: usize, decl: &str) {
+        if found != expected {
+            self.errors.push(WfError::Arity {
+                name: name.to_string(),
+                expected,
+                found,
+                decl: decl.to_string(),
+            });
+        }
+    }
+    fn check_bound(&mut self, b: &Bound, decl: &str) {
+        let mut atoms: Vec<_> = b.neg.iter().collect();
+        for f in &b.formulas {
+            f.atoms(&mut atoms);
+        }
+        for t in atoms {
+            self.check_trait(t, decl);
+            self.check_no_assoc(t, decl);
+        }
+        for t in &b.pos {
+            self.check_trait(t, decl);
+            if !self.in_impl {
+                self.check_no_assoc(t, decl);
+            }
+        }
+    }
+    fn check_params(&mut self, params: &[Param], decl: &str) {
+        for b in params.iter().filter_map(|p| p.bound.as_ref()) {
+            self.check_bound(b, decl);
+        }
+        for d in params.iter().filter_map(|p| p.default.as_ref()) {
+            self.check_type(d, decl);
+        }
+    }
+    fn check_predicates(&mut self, predicates: &[Predicate], decl: &str) {
+        for p in predicates {
+            match p {
+                Predicate::Eq(l, r) | Predicate::Ne(l, r) => {
+                    self.check_type(l, decl);
+                    self.check_type(r, decl);
+                }
+                Predicate::Bound(t, b) => {
+                    self.check_type(t, decl);
+                    self.check_bound(b, decl);
+                }
+            }
+        }
+    }
+}
+
+/// 衝突の検査の前に、構造体・トレイトの使用のパラメータ数が宣言と一致するかを調べる。
+/// 見つかった誤りを全て返す
+///
+/// 宣言されていない名前は名前解決で報告される
+pub fn check_program(program: &Program) -> Vec<WfError> {
+    let mut env = WfEnv {
+        program,
+        errors: Vec::new(),
+        in_impl: false,
+    };
+    for s in &program.structs {
+        let decl = s.to_string();
+        env.check_params(&s.params, &decl);
+        env.check_predicates(&s.predicates, &decl);
+    }
+    for t in &program.traits {
+        let decl = t.to_string();
+        env.check_params(&t.params, &decl);
+        if let Some(b) = &t.supertraits {
+            env.check_bound(b, &decl);
+        }
+        env.check_predicates(&t.predicates, &decl);
+    }
+    env.in_impl = true;
+    for i in &program.impls {
+        let decl = i.to_string();
+        env.check_params(&i.params, &decl);
+        env.check_trait(&i.trait_exp, &decl);
+        env.check_no_assoc(&i.trait_exp, &decl);
+        env.check_type(&i.impl_for, &decl);
+        for (_, t) in &i.assoc {
+            env.check_type(t, &decl);
+        }
+        env.check_predicates(&i.predicates, &decl);
+    }
+    env.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{lower, parser};
+
+    /// main と同じく、名前解決と整形式性の検査を続けて行う
+    fn errors(src: &str) -> Vec<String> {
+        let program = parser::program(src).expect("Parse error").1;
+        lower(&program).1
+    }
+
+    #[test]
+    fn name_and_arity_errors_reported_together() {
+        let src = "struct Vec<T>;
+trait From<T> {}
+trait A: From + B {}
+impl From<Vec> for Vec<X> {}
+impl<T: From<T, T> + C> From<Vec> for Vec<T, T> {}
+";
+        assert_eq!(
+            errors(src),
+            vec![
+                "error: undefined trait B in trait A: From + B",
+                "error: undefined struct X in impl From<Vec> for Vec<X>",
+                "error: undefined trait C in impl<T: From<T, T> + C> From<Vec> for Vec<T, T>",
+                "error: From takes 1 params but 0 given in trait A: From",
+                "error: Vec takes 1 params but 0 given in impl From<Vec> for Vec<X>",
+                "error: From takes 1 params but 2 given in impl<T: From<T, T> + C> From<Vec> for Vec<T, T>",
+                "error: Vec takes 1 params but 0 given in impl<T: From<T, T> + C> From<Vec> for Vec<T, T>",
+                "error: Vec takes 1 params but 2 given in impl<T: From<T, T> + C> From<Vec> for Vec<T, T>",
+            ]
+        );
+    }
+
+    #[test]
+    fn well_formed_program() {
+        let src = "struct Vec<T>;
+trait From<T> {}
+trait A: From<Self> {}
+impl<T: From<T>> From<Vec<T>> for Vec<T> {}
+";
+        assert!(errors(src).is_empty());
+    }
+
+    #[test]
+    fn projections_only_in_impls() {
+        let src = "trait Iterator { type Item; }
+trait P<T> {}
+trait A: Iterator + P<<Self as Iterator>::Item> {}
+struct S<T> where T: P<<T as Iterator>::Item>;
+";
+        assert_eq!(
+            errors(src),
+            vec![
+                "error: projection <T as Iterator>::Item is only allowed in impls, found in struct S<T> where T: P<<T as Iterator>::Item>",
+                "error: projection <Self as Iterator>::Item is only allowed in impls, found in trait A: Iterator + P<<Self as Iterator>::Item>",
+            ]
+        );
+    }
+}
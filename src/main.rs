@@ -1,13 +1,88 @@
 mod check;
 
 fn main() {
-    let args: Vec<_> = std::env::args().collect();
+    let mut options = check::checker::CheckOptions::default();
+    let mut file = None;
+    let mut coverage = None;
+    let mut reference = Vec::new();
+    let mut resolve = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--closed-world" => options.closed_world = true,
+            "--blanket-depth" => {
+                let depth = args.next().expect("Depth required");
+                options.blanket_depth = Some(depth.parse().expect("Invalid depth"));
+            }
+            "--existential" => options.existential = true,
+            "--resolve" => resolve = true,
+            "--coverage" => coverage = Some(args.next().expect("Trait name required")),
+            "--reference" => {
+                let bound = args.next().expect("Reference bound required");
+                let param = check::parser::param(check::parser::extend_trait_bound)(&bound)
+                    .expect("Reference bound parse error")
+                    .1;
+                reference.push(param);
+            }
+            _ if arg.starts_with("--") => panic!("Unknown option {}", arg),
+            _ => file = Some(arg),
+        }
+    }
     let contents =
-        std::fs::read_to_string(args.get(1).expect("Argument required")).expect("File read error");
+        std::fs::read_to_string(file.expect("Argument required")).expect("File read error");
     let program = check::parser::program(&contents).expect("Parse error").1;
     // println!("program: {:?}", program);
-    let results = check::checker::Checker::new().check(program).unwrap();
+    // 名前と整形式性の誤りはまとめて報告する
+    let (lowered, diagnostics) = check::lower(&program);
+    for d in &diagnostics {
+        eprintln!("{}", d);
+    }
+    let Some(lowered) = lowered else {
+        std::process::exit(1);
+    };
+    let reference = match check::names::lower_params(&program, &reference) {
+        Ok(reference) => reference,
+        Err(diagnostics) => {
+            for d in diagnostics {
+                eprintln!("{}", d);
+            }
+            std::process::exit(1);
+        }
+    };
+    if resolve {
+        let resolution = check::checker::resolve(program, options).unwrap_or_else(|e| {
+            eprintln!("error: {:#}", e);
+            std::process::exit(1);
+        });
+        print!("{}", resolution.program);
+        for e in resolution.edits {
+            eprintln!("added: {}", e);
+        }
+        for (im, by) in resolution.removed {
+            eprintln!("removed: {} (shadowed by {})", im, by);
+        }
+        return;
+    }
+    let mut checker = check::checker::Checker::with_options(options);
+    let results = checker.check(lowered).unwrap();
     for result in results {
         println!("{:?}:\n  {}\n  {}", result.0, result.1, result.2);
+        for overlap in result.3 {
+            println!("  overlap: {}", overlap);
+        }
+    }
+    for d in checker.diagnostics() {
+        println!("{}", d);
+    }
+    if let Some(trait_name) = coverage {
+        let reports = checker
+            .coverage(&trait_name, &reference)
+            .unwrap_or_else(|e| {
+                eprintln!("error: {:#}", e);
+                std::process::exit(1);
+            });
+        for report in reports {
+            print!("{}", report);
+        }
     }
 }